@@ -1,7 +1,13 @@
 use std::error::Error;
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+use crate::block_storage::{BadBlockPolicy, BlocksFormat};
+use crate::config::Network;
+use crate::inspect_block::InspectFormat;
+use crate::module_cache::ModuleCacheMode;
 
 #[derive(Debug, Clone, Parser)]
 #[command(name = "slot_replayer_try_1")]
@@ -12,23 +18,120 @@ pub struct Cli {
         long = "path",
         help = "Path of an existing db (e.g: /tmp/massa_8_g4j_3n/massa-node/storage/ledger/rocks_db"
     )]
-    pub(crate) db_path: PathBuf,
+    pub(crate) db_path: Option<PathBuf>,
     #[arg(
         short = 'r',
         long = "initial_roll_path",
         help = "Filepath to initial_rolls.json"
     )]
-    pub(crate) initial_rolls_path: PathBuf,
+    pub(crate) initial_rolls_path: Option<PathBuf>,
+    #[arg(
+        long = "network",
+        help = "Network of the db backups & dumped blocks (chain id, genesis timestamp & key...)",
+        value_enum,
+        default_value_t = Network::Mainnet
+    )]
+    pub(crate) network: Network,
+    #[arg(
+        long = "network_config",
+        alias = "network-config",
+        help = "TOML file with the network constants (chain_id, genesis_timestamp, genesis_key, t0, initial_draw_seed, roll_price, block_reward), required for --network custom (all constants) & buildnet (genesis_timestamp & genesis_key), overrides the profile values otherwise"
+    )]
+    pub(crate) network_config: Option<PathBuf>,
+    #[arg(
+        long = "node_config",
+        alias = "node-config",
+        help = "massa-node config.toml to read the execution, ledger & selector settings from (defaults are used for the missing values)"
+    )]
+    pub(crate) node_config: Option<PathBuf>,
     #[command(subcommand)]
     pub(crate) command: Commands,
 }
 
+impl Cli {
+    /// Exit with a usage error if an argument required by the command is missing
+    ///
+    /// Note: --path & --initial_roll_path are given before the command, so clap cannot require them per command
+    pub(crate) fn check_required_args(&self) {
+        let (needs_db_path, needs_initial_rolls) = match &self.command {
+            Commands::ListSnapshot => (true, false),
+            // a resumed replay uses the command line of its manifest
+            Commands::Replay(args) => (args.resume.is_none(), args.resume.is_none()),
+            Commands::Bisect(_) | Commands::CompareGas(_) | Commands::Sweep(_) => (true, true),
+            Commands::CompareDigests(_)
+            | Commands::ConvertBlocks(_)
+            | Commands::BlocksCoverage(_)
+            | Commands::InspectBlock(_)
+            | Commands::VerifyBlocks(_) => (false, false),
+        };
+
+        let mut missing = Vec::new();
+        if needs_db_path && self.db_path.is_none() {
+            missing.push("--path <DB_PATH>");
+        }
+        if needs_initial_rolls && self.initial_rolls_path.is_none() {
+            missing.push("--initial_roll_path <INITIAL_ROLLS_PATH>");
+        }
+        if !missing.is_empty() {
+            Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!(
+                        "the following required arguments were not provided for this command:\n  {}",
+                        missing.join("\n  ")
+                    ),
+                )
+                .exit();
+        }
+    }
+
+    /// Checked by `check_required_args`
+    pub(crate) fn db_path(&self) -> &PathBuf {
+        self.db_path.as_ref().expect("--path checked at startup")
+    }
+
+    /// Checked by `check_required_args`
+    pub(crate) fn initial_rolls_path(&self) -> &PathBuf {
+        self.initial_rolls_path
+            .as_ref()
+            .expect("--initial_roll_path checked at startup")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Subcommand)]
 pub(crate) enum Commands {
     #[command(about = "List snapshot (& display info)")]
     ListSnapshot,
     #[command(about = "Replay blocks (from a db backup and dumped blocks)")]
     Replay(ReplayArgs),
+    #[command(about = "Compare two digest files (written by replay --digest)")]
+    CompareDigests(CompareDigestsArgs),
+    #[command(
+        about = "Find the first slot where the replayed state diverges from a reference (digest file or db backup)"
+    )]
+    Bisect(BisectArgs),
+    #[command(
+        about = "Convert dumped blocks between the file layout (.bin files) and the RocksDB layout"
+    )]
+    ConvertBlocks(ConvertBlocksArgs),
+    #[command(
+        about = "Show the slots covered by the dumped blocks, and from which backups a replay can run without gaps"
+    )]
+    BlocksCoverage(BlocksCoverageArgs),
+    #[command(about = "Decode and print a dumped block (header, endorsements & operations)")]
+    InspectBlock(InspectBlockArgs),
+    #[command(
+        about = "Check the dumped blocks (signatures, ids, operation merkle root & parents) and report every failure"
+    )]
+    VerifyBlocks(VerifyBlocksArgs),
+    #[command(
+        about = "Replay the same blocks under two gas cost tables and report the operations & blocks whose execution or gas used changed"
+    )]
+    CompareGas(CompareGasArgs),
+    #[command(
+        about = "Replay the same blocks for every combination of execution cache settings and compare the performance"
+    )]
+    Sweep(SweepArgs),
 }
 
 #[derive(Debug, Clone, PartialEq, Args)]
@@ -36,22 +139,433 @@ pub struct ReplayArgs {
     #[arg(
         short = 'b',
         long = "blocks",
-        help = "Folder where to find block dumped as .bin file"
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)",
+        required_unless_present = "resume"
+    )]
+    pub(crate) dump_block_path: Option<PathBuf>,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
+    #[arg(
+        long = "backup",
+        help = "Folder where to find db backup (if not specified, the newest backup in --path at or before --from_slot is used)",
+        required_unless_present_any = ["from_slot", "resume"]
+    )]
+    pub(crate) db_backup_path: Option<PathBuf>,
+    #[arg(
+        long = "from_slot",
+        help = "First slot to record (outputs, digests, traces), slots before are replayed silently. ex: `--from_slot 40,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) from_slot: Option<(u64, u8)>,
+    #[arg(
+        long = "until_slot",
+        help = "Replay from last slot defined into backup to given slot period, if not specified will replay until blocks are available. ex: `--slot 40,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) until_slot: Option<(u64, u8)>,
+    #[arg(
+        long = "execution_timeout",
+        help = "Max number of seconds to wait for the next finalized slot before giving up (execution stalled)",
+        default_value_t = 300
+    )]
+    pub(crate) execution_timeout: u64,
+    #[arg(
+        long = "digest",
+        help = "Append the final state hashes of every finalized slot to this file"
+    )]
+    pub(crate) digest_path: Option<PathBuf>,
+    #[arg(
+        long = "reference_digest",
+        help = "Compare the final state of every finalized slot with this digest file, stop at the first divergence"
+    )]
+    pub(crate) reference_digest: Option<PathBuf>,
+    #[arg(
+        long = "reference_backup",
+        help = "Db backup to check the final state against (in addition to the backups found in --path)"
+    )]
+    pub(crate) reference_backup: Option<PathBuf>,
+    #[arg(
+        long = "output",
+        help = "Append the execution output (block id, events, state changes) of every finalized slot to this file, as json lines"
+    )]
+    pub(crate) output_path: Option<PathBuf>,
+    #[arg(
+        long = "measure_gas",
+        help = "Measure the gas used by the smart contract operations (read-only execution of each operation before its block is fed, blocks fed one at a time), written to --output & --perf_report"
+    )]
+    pub(crate) measure_gas: bool,
+    #[arg(
+        long = "traces",
+        help = "Folder where to write the abi call stacks of every finalized slot (one json lines file per slot, one object per operation & async message)"
+    )]
+    pub(crate) traces_path: Option<PathBuf>,
+    #[arg(
+        long = "trace_address",
+        help = "Only keep the call stacks of the operations & async messages sent by, targeting or calling this address (can be repeated)"
+    )]
+    pub(crate) trace_addresses: Vec<String>,
+    #[arg(
+        long = "trace_operation",
+        help = "Only keep the call stacks of this operation id (can be repeated)"
+    )]
+    pub(crate) trace_operation_ids: Vec<String>,
+    #[arg(
+        long = "snapshot_at",
+        alias = "snapshot-at",
+        help = "Write a db backup (in --path) at the end of the given slot (can be repeated). ex: `--snapshot_at 40,31`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) snapshot_at: Vec<(u64, u8)>,
+    #[arg(
+        long = "snapshot_every_cycle",
+        alias = "snapshot-every-cycle",
+        help = "Write a db backup (in --path) at the end of every cycle"
+    )]
+    pub(crate) snapshot_every_cycle: bool,
+    #[arg(
+        long = "workdir",
+        help = "Folder where to run the replay, with periodic checkpoints so it can be resumed (see --resume)"
+    )]
+    pub(crate) workdir: Option<PathBuf>,
+    #[arg(
+        long = "checkpoint_every_cycles",
+        help = "Number of cycles between two checkpoints (with --workdir)",
+        default_value_t = 1
+    )]
+    pub(crate) checkpoint_every_cycles: u64,
+    #[arg(
+        long = "resume",
+        help = "Resume an interrupted replay from the last checkpoint of its workdir (other replay arguments are read from the workdir)"
+    )]
+    pub(crate) resume: Option<PathBuf>,
+    #[arg(
+        long = "verify_blocks",
+        help = "Check the dumped blocks to replay (see verify-blocks command) before replaying, abort if one is invalid"
+    )]
+    pub(crate) verify_blocks: bool,
+    #[arg(
+        long = "on_bad_block",
+        alias = "on-bad-block",
+        help = "What to do with a dumped block that cannot be decoded or converted (skip: log it and replay the slot as a missed slot)",
+        value_enum,
+        default_value_t = BadBlockPolicy::Abort
+    )]
+    pub(crate) on_bad_block: BadBlockPolicy,
+    #[arg(
+        long = "gas_costs",
+        alias = "gas-costs",
+        help = "Folder with the gas cost tables (abi_gas_costs.json & wasm_gas_costs.json), default: gas_costs folder next to initial_rolls.json"
+    )]
+    pub(crate) gas_costs_path: Option<PathBuf>,
+    #[arg(
+        long = "perf_report",
+        help = "Write the performance of the replay to this json file: per slot latency, operations, gas used (with --measure_gas) & async messages executed, percentiles, slowest slots, peak RSS, final state hash"
+    )]
+    pub(crate) perf_report_path: Option<PathBuf>,
+    #[arg(
+        long = "module_cache",
+        alias = "module-cache",
+        help = "Module cache (compiled bytecodes) of the execution: cold (empty cache in the replay workspace), warm (cache of the --workdir, kept between replays) or a folder",
+        default_value_t = ModuleCacheMode::Cold
+    )]
+    pub(crate) module_cache: ModuleCacheMode,
+    #[arg(
+        long = "precompile_modules",
+        alias = "precompile-modules",
+        help = "Compile the bytecode of every address of the backup ledger into the module cache before the replay starts (not timed)"
+    )]
+    pub(crate) precompile_modules: bool,
+}
+
+impl ReplayArgs {
+    pub(crate) fn dump_block_path(&self) -> &PathBuf {
+        self.dump_block_path
+            .as_ref()
+            .expect("Argument --blocks is required for this command")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct BisectArgs {
+    #[arg(
+        short = 'b',
+        long = "blocks",
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)"
     )]
     pub(crate) dump_block_path: PathBuf,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
+    #[arg(
+        long = "reference_digest",
+        help = "Digest file written by a reference replay",
+        required_unless_present = "reference_backup",
+        conflicts_with = "reference_backup"
+    )]
+    pub(crate) reference_digest: Option<PathBuf>,
+    #[arg(
+        long = "reference_backup",
+        help = "Db backup taken by a reference node"
+    )]
+    pub(crate) reference_backup: Option<PathBuf>,
+    #[arg(
+        long = "reference_replayer",
+        help = "Replayer built with the reference massa version: with --reference_backup, its digests are used to find the exact divergent slot between two backups",
+        requires = "reference_backup"
+    )]
+    pub(crate) reference_replayer: Option<PathBuf>,
+    #[arg(
+        long = "execution_timeout",
+        help = "Max number of seconds to wait for the next finalized slot before giving up (execution stalled)",
+        default_value_t = 300
+    )]
+    pub(crate) execution_timeout: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct ConvertBlocksArgs {
+    #[arg(long = "input", help = "Folder of the dumped blocks to convert")]
+    pub(crate) input: PathBuf,
+    #[arg(
+        long = "input_format",
+        help = "Layout of the --input folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) input_format: BlocksFormat,
+    #[arg(long = "output", help = "Folder where to write the converted blocks")]
+    pub(crate) output: PathBuf,
+    #[arg(
+        long = "output_format",
+        help = "Layout of the --output folder (auto: the other layout than the input one)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) output_format: BlocksFormat,
+    #[arg(
+        long = "from_slot",
+        help = "First slot to convert. ex: `--from_slot 40,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) from_slot: Option<(u64, u8)>,
+    #[arg(
+        long = "until_slot",
+        help = "Last slot to convert. ex: `--until_slot 45,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) until_slot: Option<(u64, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct BlocksCoverageArgs {
+    #[arg(
+        short = 'b',
+        long = "blocks",
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)"
+    )]
+    pub(crate) dump_block_path: PathBuf,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
+    #[arg(
+        long = "backup",
+        help = "Only check this db backup (default: every backup found in --path)"
+    )]
+    pub(crate) db_backup_path: Option<PathBuf>,
+    #[arg(
+        long = "max_gap",
+        help = "Max number of consecutive slots without block considered as missed slots (longer gaps are missing dumps)",
+        default_value_t = 32
+    )]
+    pub(crate) max_gap: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct InspectBlockArgs {
+    #[arg(help = "Slot of the block. ex: `40,2`", value_parser = parse_slot)]
+    pub(crate) slot: (u64, u8),
+    #[arg(
+        short = 'b',
+        long = "blocks",
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)"
+    )]
+    pub(crate) dump_block_path: PathBuf,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
+    #[arg(
+        long = "format",
+        help = "Output format",
+        value_enum,
+        default_value_t = InspectFormat::Table
+    )]
+    pub(crate) format: InspectFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct VerifyBlocksArgs {
+    #[arg(
+        short = 'b',
+        long = "blocks",
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)"
+    )]
+    pub(crate) dump_block_path: PathBuf,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
+    #[arg(
+        long = "from_slot",
+        help = "First slot to check. ex: `--from_slot 40,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) from_slot: Option<(u64, u8)>,
+    #[arg(
+        long = "until_slot",
+        help = "Last slot to check. ex: `--until_slot 45,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) until_slot: Option<(u64, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct CompareGasArgs {
+    #[arg(
+        short = 'b',
+        long = "blocks",
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)"
+    )]
+    pub(crate) dump_block_path: PathBuf,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
     #[arg(long = "backup", help = "Folder where to find db backup")]
     pub(crate) db_backup_path: PathBuf,
     #[arg(
         long = "until_slot",
-        help = "Replay from last slot defined into backup to given slot period, if not specified will replay until blocks are available. ex: `--slot 40,2`",
+        help = "Last slot to replay, if not specified will replay until blocks are available. ex: `--until_slot 40,2`",
         value_parser = parse_slot,
     )]
     pub(crate) until_slot: Option<(u64, u8)>,
+    #[arg(
+        long = "gas_costs_a",
+        help = "Folder with the reference gas cost tables (abi_gas_costs.json & wasm_gas_costs.json)"
+    )]
+    pub(crate) gas_costs_a: PathBuf,
+    #[arg(
+        long = "gas_costs_b",
+        help = "Folder with the gas cost tables to assess"
+    )]
+    pub(crate) gas_costs_b: PathBuf,
+    #[arg(
+        long = "output_dir",
+        help = "Folder where to keep the execution outputs of both replays (outputs_a.ndjson & outputs_b.ndjson), a temp folder otherwise"
+    )]
+    pub(crate) output_dir: Option<PathBuf>,
+    #[arg(
+        long = "execution_timeout",
+        help = "Max number of seconds to wait for the next finalized slot before giving up (execution stalled)",
+        default_value_t = 300
+    )]
+    pub(crate) execution_timeout: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct SweepArgs {
+    #[arg(
+        short = 'b',
+        long = "blocks",
+        help = "Folder where to find the dumped blocks (.bin files or RocksDB)"
+    )]
+    pub(crate) dump_block_path: PathBuf,
+    #[arg(
+        long = "blocks_format",
+        alias = "blocks-format",
+        help = "Layout of the --blocks folder (auto: RocksDB if it contains a CURRENT file, .bin files otherwise)",
+        value_enum,
+        default_value_t = BlocksFormat::Auto
+    )]
+    pub(crate) blocks_format: BlocksFormat,
+    #[arg(long = "backup", help = "Folder where to find db backup")]
+    pub(crate) db_backup_path: PathBuf,
+    #[arg(
+        long = "until_slot",
+        help = "Last slot to replay, if not specified will replay until blocks are available. ex: `--until_slot 40,2`",
+        value_parser = parse_slot,
+    )]
+    pub(crate) until_slot: Option<(u64, u8)>,
+    #[arg(
+        long = "lru_cache_size",
+        help = "Values of execution.lru_cache_size to try, ex: `--lru_cache_size 100,200,400` (default: the node setting)",
+        value_delimiter = ','
+    )]
+    pub(crate) lru_cache_sizes: Vec<u32>,
+    #[arg(
+        long = "hd_cache_size",
+        help = "Values of execution.hd_cache_size to try (default: the node setting)",
+        value_delimiter = ','
+    )]
+    pub(crate) hd_cache_sizes: Vec<usize>,
+    #[arg(
+        long = "snip_amount",
+        help = "Values of execution.snip_amount to try (default: the node setting)",
+        value_delimiter = ','
+    )]
+    pub(crate) snip_amounts: Vec<usize>,
+    #[arg(
+        long = "output_dir",
+        help = "Folder where to keep the node config, module cache & perf report of every run, a temp folder otherwise"
+    )]
+    pub(crate) output_dir: Option<PathBuf>,
+    #[arg(
+        long = "execution_timeout",
+        help = "Max number of seconds to wait for the next finalized slot before giving up (execution stalled)",
+        default_value_t = 300
+    )]
+    pub(crate) execution_timeout: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct CompareDigestsArgs {
+    #[arg(help = "First digest file")]
+    pub(crate) digest_a: PathBuf,
+    #[arg(help = "Second digest file")]
+    pub(crate) digest_b: PathBuf,
 }
 
 fn parse_slot(s: &str) -> Result<(u64, u8), Box<dyn Error + Send + Sync + 'static>> {
-    
-    let (period_, thread_) = s.split_once(',')
+    let (period_, thread_) = s
+        .split_once(',')
         .ok_or("Slot must be specified as PERIOD,THREAD, ex: `--slt 40,2`")?;
     let period = period_.parse::<u64>()?;
     let thread = thread_.parse::<u8>()?;
@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+use massa_async_pool::{AsyncMessage, AsyncMessageId};
+use massa_execution_exports::ExecutionOutput;
+use massa_final_state::FinalStateController;
+use massa_models::slot::Slot;
+use massa_models::types::{Applicable, SetUpdateOrDelete};
+
+/// Follow the async pool through the state changes of the finalized slots, to tell the
+/// messages executed at a slot from the expired ones
+pub struct AsyncMessageTracker {
+    messages: BTreeMap<AsyncMessageId, AsyncMessage>,
+}
+
+impl AsyncMessageTracker {
+    /// Start from the async pool of the final state
+    ///
+    /// Note: must be called before the execution finalizes any slot
+    pub fn new(final_state: &dyn FinalStateController) -> Self {
+        let async_pool = final_state.get_async_pool();
+        let ids: Vec<&AsyncMessageId> = async_pool.message_info_cache.keys().collect();
+        let messages = async_pool
+            .fetch_messages(ids)
+            .into_iter()
+            .filter_map(|(id, message)| Some((*id, message?)))
+            .collect();
+        Self { messages }
+    }
+
+    /// Apply the async pool changes of a finalized slot (slots in order) and return the messages
+    /// executed at this slot, in execution order (message id order)
+    ///
+    /// A message is executed if it is removed from the pool while executable (triggered and
+    /// within its validity period), otherwise it expired. A message dropped because the pool
+    /// is full is counted as executed (not told apart in the state changes).
+    pub fn on_finalized(&mut self, output: &ExecutionOutput) -> Vec<AsyncMessage> {
+        let mut executed = Vec::new();
+        for (id, change) in output.state_changes.async_pool_changes.0.iter() {
+            match change {
+                SetUpdateOrDelete::Set(message) => {
+                    self.messages.insert(*id, message.clone());
+                }
+                SetUpdateOrDelete::Update(update) => {
+                    if let Some(message) = self.messages.get_mut(id) {
+                        message.apply(update.clone());
+                    }
+                }
+                SetUpdateOrDelete::Delete => {
+                    if let Some(message) = self.messages.remove(id) {
+                        if is_executable(&message, output.slot) {
+                            executed.push(message);
+                        }
+                    }
+                }
+            }
+        }
+        executed
+    }
+}
+
+/// Same condition as the execution when it takes the messages to execute at a slot
+fn is_executable(message: &AsyncMessage, slot: Slot) -> bool {
+    message.can_be_executed && message.validity_start <= slot && slot < message.validity_end
+}
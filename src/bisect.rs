@@ -0,0 +1,344 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::ValueEnum;
+use massa_models::slot::Slot;
+
+use crate::args::BisectArgs;
+use crate::config::config_args;
+use crate::digest::{first_divergence, read_digest_file};
+use crate::snapshot::{list_snapshots, read_snapshot, Snapshot};
+use crate::EXIT_DIVERGENCE;
+
+enum ReplayResult {
+    Ok,
+    Diverged,
+    Failed(Option<i32>),
+}
+
+/// Run a replay (in a child process, so each replay starts from a clean state)
+///
+/// `replayer` is this replayer, or the reference one
+fn run_replay(
+    replayer: &Path,
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    args: &BisectArgs,
+    backup: &Path,
+    until_slot: Slot,
+    extra_args: Vec<(&str, &Path)>,
+) -> ReplayResult {
+    let mut command = Command::new(replayer);
+    command
+        .arg("--path")
+        .arg(db_path)
+        .arg("--initial_roll_path")
+        .arg(initial_rolls_path)
+        .args(config_args())
+        .arg("replay")
+        .arg("--blocks")
+        .arg(&args.dump_block_path)
+        .arg("--blocks_format")
+        .arg(args.blocks_format.to_possible_value().unwrap().get_name())
+        .arg("--backup")
+        .arg(backup)
+        .arg("--until_slot")
+        .arg(format!("{},{}", until_slot.period, until_slot.thread))
+        .arg("--execution_timeout")
+        .arg(args.execution_timeout.to_string());
+    for (name, value) in extra_args {
+        command.arg(name).arg(value);
+    }
+
+    println!(
+        "Replaying ({:?}) from backup {:?} until slot {}...",
+        replayer, backup, until_slot
+    );
+    let status = command.status().expect("Failed to run replay");
+    match status.code() {
+        Some(0) => ReplayResult::Ok,
+        Some(EXIT_DIVERGENCE) => ReplayResult::Diverged,
+        code => ReplayResult::Failed(code),
+    }
+}
+
+fn current_replayer() -> PathBuf {
+    std::env::current_exe().expect("Cannot find current executable")
+}
+
+pub fn bisect(db_path: &Path, initial_rolls_path: &Path, args: &BisectArgs) {
+    let snapshots = list_snapshots(db_path);
+    if snapshots.is_empty() {
+        eprintln!("Cannot find any backup in {:?}", db_path);
+        std::process::exit(1);
+    }
+
+    let found = match (&args.reference_digest, &args.reference_backup) {
+        (Some(reference_digest), _) => bisect_with_digest(
+            db_path,
+            initial_rolls_path,
+            args,
+            &snapshots,
+            reference_digest,
+        ),
+        (None, Some(reference_backup)) => bisect_with_backup(
+            db_path,
+            initial_rolls_path,
+            args,
+            &snapshots,
+            reference_backup,
+        ),
+        (None, None) => unreachable!("clap requires a reference"),
+    };
+
+    if found {
+        std::process::exit(EXIT_DIVERGENCE);
+    }
+}
+
+/// Replay (step by step) from the latest backup consistent with the reference digests,
+/// the replay stops at the first divergent slot and prints it
+///
+/// Return true if a divergence was found
+fn bisect_with_digest(
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    args: &BisectArgs,
+    snapshots: &[Snapshot],
+    reference_digest: &Path,
+) -> bool {
+    let reference = read_digest_file(reference_digest).unwrap_or_else(|e| {
+        eprintln!("Failed to read reference digest file: {}", e);
+        std::process::exit(1);
+    });
+    let (Some(first_slot), Some(last_slot)) = (
+        reference.keys().next().copied(),
+        reference.keys().last().copied(),
+    ) else {
+        eprintln!("Reference digest file {:?} is empty", reference_digest);
+        std::process::exit(1);
+    };
+
+    let mut start: Option<&Snapshot> = None;
+    for snapshot in snapshots.iter().filter(|s| s.slot < last_slot) {
+        match reference
+            .get(&snapshot.slot)
+            .and_then(|digest| digest.final_state.as_ref())
+        {
+            Some(hash) if *hash == snapshot.hash.to_string() => start = Some(snapshot),
+            Some(_) => {
+                println!(
+                    "Backup {:?} (slot {}) does not match the reference",
+                    snapshot.path, snapshot.slot
+                );
+                break;
+            }
+            None if snapshot.slot < first_slot => start = Some(snapshot),
+            None => {}
+        }
+    }
+
+    let Some(start) = start else {
+        eprintln!(
+            "No backup consistent with the reference digests (from slot {} to slot {})",
+            first_slot, last_slot
+        );
+        std::process::exit(1);
+    };
+
+    match run_replay(
+        &current_replayer(),
+        db_path,
+        initial_rolls_path,
+        args,
+        &start.path,
+        last_slot,
+        vec![("--reference_digest", reference_digest)],
+    ) {
+        ReplayResult::Ok => {
+            println!(
+                "No divergence found from slot {} to slot {}",
+                start.slot, last_slot
+            );
+            false
+        }
+        ReplayResult::Diverged => true,
+        ReplayResult::Failed(code) => {
+            eprintln!("Replay failed (exit code: {:?})", code);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Bisect over the backups (up to the reference backup): replay from a backup matching the
+/// reference to a backup half way, keep the half where the replayed state diverges, until the
+/// divergence is narrowed to two consecutive backups (then, with --reference_replayer, find the
+/// exact slot in this interval)
+///
+/// Note: a divergence is assumed to persist (every later backup differs from the replayed state)
+///
+/// Return true if a divergence was found
+fn bisect_with_backup(
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    args: &BisectArgs,
+    snapshots: &[Snapshot],
+    reference_backup: &Path,
+) -> bool {
+    let reference = read_snapshot(reference_backup.to_path_buf());
+    let mut checkpoints: Vec<&Snapshot> = snapshots
+        .iter()
+        .filter(|s| s.slot < reference.slot)
+        .collect();
+    if checkpoints.is_empty() {
+        eprintln!(
+            "No backup older than the reference backup (slot {})",
+            reference.slot
+        );
+        std::process::exit(1);
+    }
+    checkpoints.push(&reference);
+
+    // the replayed state matches checkpoints[good], diverges at checkpoints[bad] (if tested)
+    let (mut good, mut bad) = (0, checkpoints.len() - 1);
+    let mut bad_tested = false;
+    while bad - good > 1 || !bad_tested {
+        let target = if bad - good > 1 {
+            good + (bad - good) / 2
+        } else {
+            bad
+        };
+        let (from, to) = (checkpoints[good], checkpoints[target]);
+        println!(
+            "Bisecting over {} backup intervals: replaying from slot {} to slot {}",
+            bad - good,
+            from.slot,
+            to.slot
+        );
+        let mut extra_args = Vec::new();
+        if to.slot == reference.slot {
+            extra_args.push(("--reference_backup", reference_backup));
+        }
+        match run_replay(
+            &current_replayer(),
+            db_path,
+            initial_rolls_path,
+            args,
+            &from.path,
+            to.slot,
+            extra_args,
+        ) {
+            ReplayResult::Ok if target == bad => {
+                println!(
+                    "No divergence found up to the reference backup (slot {})",
+                    reference.slot
+                );
+                return false;
+            }
+            ReplayResult::Ok => good = target,
+            ReplayResult::Diverged => {
+                bad = target;
+                bad_tested = true;
+            }
+            ReplayResult::Failed(code) => {
+                eprintln!("Replay failed (exit code: {:?})", code);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let (from, to) = (checkpoints[good], checkpoints[bad]);
+    println!(
+        "First divergence between backup slot {} (excluded) and backup slot {} (included)",
+        from.slot, to.slot
+    );
+    match args.reference_replayer.as_ref() {
+        Some(reference_replayer) => find_divergent_slot(
+            db_path,
+            initial_rolls_path,
+            args,
+            from,
+            to.slot,
+            reference_replayer,
+        ),
+        None => println!(
+            "Exact divergent slot not searched (interval only): add --reference_replayer (a replayer built with the reference massa version) to find it"
+        ),
+    }
+    true
+}
+
+/// Replay the interval with the reference replayer (writing its digests), then with this
+/// replayer against these digests: the replay stops at the first divergent slot and prints
+/// its block, operations & state changes
+fn find_divergent_slot(
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    args: &BisectArgs,
+    from: &Snapshot,
+    until_slot: Slot,
+    reference_replayer: &Path,
+) {
+    // keep the temp dir alive until the end of the search
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let reference_digest = temp_dir.path().join("reference_digest.tsv");
+    let replayed_digest = temp_dir.path().join("replayed_digest.tsv");
+
+    match run_replay(
+        reference_replayer,
+        db_path,
+        initial_rolls_path,
+        args,
+        &from.path,
+        until_slot,
+        vec![("--digest", &reference_digest)],
+    ) {
+        ReplayResult::Ok => {}
+        ReplayResult::Diverged => {
+            println!("The reference replayer does not match the backups either");
+        }
+        ReplayResult::Failed(code) => {
+            eprintln!("Reference replay failed (exit code: {:?})", code);
+            std::process::exit(1);
+        }
+    }
+
+    if let ReplayResult::Failed(code) = run_replay(
+        &current_replayer(),
+        db_path,
+        initial_rolls_path,
+        args,
+        &from.path,
+        until_slot,
+        vec![
+            ("--reference_digest", &reference_digest),
+            ("--digest", &replayed_digest),
+        ],
+    ) {
+        eprintln!("Replay failed (exit code: {:?})", code);
+        std::process::exit(1);
+    }
+
+    let digests = (
+        read_digest_file(&reference_digest),
+        read_digest_file(&replayed_digest),
+    );
+    let (reference, replayed) = match digests {
+        (Ok(reference), Ok(replayed)) => (reference, replayed),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to read digest file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match first_divergence(&reference, &replayed) {
+        Some((reference, replayed)) => println!(
+            "First divergent slot: {} ({} differ)",
+            replayed.slot,
+            reference.diff(replayed).join(", ")
+        ),
+        None => println!(
+            "Same digests as the reference replayer from slot {} to slot {}: the reference replayer diverges too",
+            from.slot, until_slot
+        ),
+    }
+}
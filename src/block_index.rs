@@ -0,0 +1,917 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, read, read_dir, remove_file, write, File, Metadata};
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::{Bound, Range, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use massa_models::config::THREAD_COUNT;
+use massa_models::slot::Slot;
+use serde::{Deserialize, Serialize};
+
+/// Index cache folder, at the root of the dumped blocks folder
+pub const INDEX_CACHE_FOLDER_NAME: &str = ".block_index";
+/// Folder entries of the index cache (the slots of each folder are in `<id>.bin` next to it)
+const INDEX_CACHE_FILE_NAME: &str = "index.json";
+const INDEX_CACHE_VERSION: u32 = 3;
+
+/// Slot in a block list file: period (u64, little endian) then thread
+const SLOT_RECORD_SIZE: usize = 9;
+
+/// Max number of skipped files printed when opening an index
+const MAX_SKIPPED_FILES_PRINTED: usize = 10;
+
+/// Size & modification time (secs, nanos) of a file when it was last checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    size: u64,
+    modified: (u64, u32),
+}
+
+impl FileStamp {
+    fn new(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            modified: metadata.modified().map(since_epoch).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockFile {
+    slot: (u64, u8),
+    stamp: FileStamp,
+}
+
+/// File not matching `block_slot_<thread>_<period>.bin`, or empty / unreadable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SkippedFile {
+    file_name: String,
+    reason: String,
+    /// None if the metadata cannot be read
+    stamp: Option<FileStamp>,
+}
+
+/// Check a file of a dumped blocks folder
+fn check_file(
+    file_name: String,
+    metadata: std::io::Result<Metadata>,
+) -> Result<BlockFile, SkippedFile> {
+    let metadata = metadata.map_err(|e| SkippedFile {
+        file_name: file_name.clone(),
+        reason: format!("cannot read metadata: {}", e),
+        stamp: None,
+    })?;
+    let stamp = FileStamp::new(&metadata);
+    let slot = parse_block_file_name(&file_name).and_then(|slot| match metadata.len() {
+        0 => Err("empty file".to_string()),
+        _ => Ok(slot),
+    });
+    match slot {
+        Ok(slot) => Ok(BlockFile {
+            slot: (slot.period, slot.thread),
+            stamp,
+        }),
+        Err(reason) => Err(SkippedFile {
+            file_name,
+            reason,
+            stamp: Some(stamp),
+        }),
+    }
+}
+
+/// Content of a folder when it was last scanned
+///
+/// The slots of its blocks are in a block list file of the cache folder (loaded when needed),
+/// except the blocks modified in the same second as the last check, kept apart to be checked again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FolderEntry {
+    /// Modification time of the folder (secs, nanos), the folder is scanned again if it changes
+    modified: (u64, u32),
+    /// Time of the last check of the folder files
+    scanned_at: (u64, u32),
+    /// Id of the block list file (`<id>.bin`), None until written
+    blocks_file: Option<u64>,
+    /// First & last slot of the block list file (None: no block)
+    listed_span: Option<((u64, u8), (u64, u8))>,
+    /// Blocks modified in the same second as the last check (not in the block list file)
+    racy_blocks: Vec<BlockFile>,
+    /// Sub folders (relative to the index root)
+    subfolders: Vec<PathBuf>,
+    skipped: Vec<SkippedFile>,
+    /// Slots of the block list file, if loaded
+    #[serde(skip)]
+    listed: Option<Vec<Slot>>,
+}
+
+impl FolderEntry {
+    fn add(&mut self, file: Result<BlockFile, SkippedFile>, listed: &mut Vec<Slot>) {
+        match file {
+            Ok(block) if is_racy(block.stamp.modified, self.scanned_at) => {
+                self.racy_blocks.push(block)
+            }
+            Ok(block) => listed.push(Slot::new(block.slot.0, block.slot.1)),
+            Err(skipped) => self.skipped.push(skipped),
+        }
+    }
+
+    /// Replace the slots of the block list file (written at the next save)
+    fn set_listed(&mut self, mut listed: Vec<Slot>) {
+        listed.sort();
+        listed.dedup();
+        self.listed_span = listed
+            .first()
+            .zip(listed.last())
+            .map(|(first, last)| ((first.period, first.thread), (last.period, last.thread)));
+        self.listed = Some(listed);
+        self.blocks_file = None;
+    }
+
+    fn sort(&mut self) {
+        self.racy_blocks.sort_by_key(|block| block.slot);
+        self.subfolders.sort();
+        self.skipped.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    }
+
+    /// Check again the files that may have changed without a change of the folder modification
+    /// time: the skipped files (empty or partially written) and the files modified in the same
+    /// second as the last check (mtime resolution can be 1 s)
+    ///
+    /// Return true if the entry must be saved
+    fn recheck(&mut self, root: &Path, relative_path: &Path) -> Result<bool, String> {
+        if self.racy_blocks.is_empty() && self.skipped.is_empty() {
+            return Ok(false);
+        }
+        let path = root.join(relative_path);
+        let racy_blocks = std::mem::take(&mut self.racy_blocks);
+        let skipped = std::mem::take(&mut self.skipped);
+
+        self.scanned_at = since_epoch(SystemTime::now());
+        // save at least once with the new check time, so the racy files are not checked again
+        let mut changed = !racy_blocks.is_empty();
+        let mut listed = Vec::new();
+        for block in racy_blocks {
+            let file_name = block_file_name(&Slot::new(block.slot.0, block.slot.1));
+            let metadata = path.join(&file_name).metadata();
+            changed |= metadata.as_ref().ok().map(FileStamp::new) != Some(block.stamp);
+            self.add(check_file(file_name, metadata), &mut listed);
+        }
+        for file in skipped {
+            let metadata = path.join(&file.file_name).metadata();
+            changed |= metadata.as_ref().ok().map(FileStamp::new) != file.stamp;
+            self.add(check_file(file.file_name, metadata), &mut listed);
+        }
+        if !listed.is_empty() {
+            let blocks_file = self.blocks_file.map(|id| blocks_file_path(root, id));
+            if let Some(blocks_file) = blocks_file {
+                listed.extend(read_blocks_file(&blocks_file)?);
+            }
+            self.set_listed(listed);
+        }
+        self.sort();
+        Ok(changed)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexCache {
+    version: u32,
+    /// Id of the next block list file
+    next_id: u64,
+    /// Scanned folders, by path relative to the index root
+    folders: BTreeMap<PathBuf, FolderEntry>,
+}
+
+/// File name of a dumped block (same as the file storage backend of the node)
+pub fn block_file_name(slot: &Slot) -> String {
+    format!("block_slot_{}_{}.bin", slot.thread, slot.period)
+}
+
+/// Parse a dumped block file name: `block_slot_<thread>_<period>.bin`
+fn parse_block_file_name(file_name: &str) -> Result<Slot, String> {
+    let (thread, period) = file_name
+        .strip_prefix("block_slot_")
+        .and_then(|s| s.strip_suffix(".bin"))
+        .and_then(|s| s.split_once('_'))
+        .ok_or_else(|| "not a block_slot_<thread>_<period>.bin file".to_string())?;
+    let thread = thread
+        .parse::<u8>()
+        .ok()
+        .filter(|thread| *thread < THREAD_COUNT)
+        .ok_or_else(|| format!("invalid thread: {}", thread))?;
+    let period = period
+        .parse::<u64>()
+        .map_err(|_| format!("invalid period: {}", period))?;
+    Ok(Slot::new(period, thread))
+}
+
+fn since_epoch(time: SystemTime) -> (u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+/// Modified in the same second as the check: may have changed after it with the same modification time
+fn is_racy(modified: (u64, u32), checked_at: (u64, u32)) -> bool {
+    modified.0 + 1 >= checked_at.0
+}
+
+fn modified(path: &Path) -> Result<(u64, u32), String> {
+    path.metadata()
+        .and_then(|m| m.modified())
+        .map(since_epoch)
+        .map_err(|e| format!("cannot read {:?} metadata: {}", path, e))
+}
+
+fn blocks_file_path(root: &Path, id: u64) -> PathBuf {
+    root.join(INDEX_CACHE_FOLDER_NAME)
+        .join(format!("{}.bin", id))
+}
+
+fn read_blocks_file(path: &Path) -> Result<Vec<Slot>, String> {
+    let content = read(path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+    if content.len() % SLOT_RECORD_SIZE != 0 {
+        return Err(format!("invalid block list file {:?}", path));
+    }
+    Ok(content
+        .chunks_exact(SLOT_RECORD_SIZE)
+        .map(|record| {
+            let period = u64::from_le_bytes(record[..8].try_into().unwrap());
+            Slot::new(period, record[8])
+        })
+        .collect())
+}
+
+fn write_blocks_file(path: &Path, slots: &[Slot]) -> Result<(), String> {
+    let mut content = Vec::with_capacity(slots.len() * SLOT_RECORD_SIZE);
+    for slot in slots {
+        content.extend_from_slice(&slot.period.to_le_bytes());
+        content.push(slot.thread);
+    }
+    write(path, content).map_err(|e| format!("cannot write {:?}: {}", path, e))
+}
+
+fn scan_folder(
+    root: &Path,
+    relative_path: &Path,
+    modified: (u64, u32),
+) -> Result<FolderEntry, String> {
+    let path = root.join(relative_path);
+    let mut entry = FolderEntry {
+        modified,
+        scanned_at: since_epoch(SystemTime::now()),
+        blocks_file: None,
+        listed_span: None,
+        racy_blocks: Vec::new(),
+        subfolders: Vec::new(),
+        skipped: Vec::new(),
+        listed: None,
+    };
+    let mut listed = Vec::new();
+    let dir = read_dir(&path).map_err(|e| format!("cannot read folder {:?}: {}", path, e))?;
+    for dir_entry in dir {
+        let dir_entry = dir_entry.map_err(|e| format!("cannot read folder {:?}: {}", path, e))?;
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        if relative_path.as_os_str().is_empty() && file_name == INDEX_CACHE_FOLDER_NAME {
+            continue;
+        }
+        let file_type = dir_entry
+            .file_type()
+            .map_err(|e| format!("cannot read {:?}: {}", dir_entry.path(), e))?;
+        if file_type.is_dir() {
+            entry.subfolders.push(relative_path.join(&file_name));
+            continue;
+        }
+        entry.add(check_file(file_name, dir_entry.metadata()), &mut listed);
+    }
+    entry.set_listed(listed);
+    entry.sort();
+    Ok(entry)
+}
+
+/// Folder with dumped blocks
+struct Folder {
+    /// Relative to the index root
+    path: PathBuf,
+    modified: (u64, u32),
+    blocks_file: Option<PathBuf>,
+    racy_slots: Vec<Slot>,
+    first: Slot,
+    last: Slot,
+    /// Slots of the blocks of the folder, sorted (loaded when needed)
+    slots: OnceLock<Vec<Slot>>,
+}
+
+/// Folders whose slot ranges overlap
+struct Cluster {
+    /// Indexes in `BlockIndex::folders`
+    folders: Range<usize>,
+    first: Slot,
+    last: Slot,
+}
+
+fn overlaps(range: &impl RangeBounds<Slot>, first: Slot, last: Slot) -> bool {
+    let after_start = match range.start_bound() {
+        Bound::Included(start) => last >= *start,
+        Bound::Excluded(start) => last > *start,
+        Bound::Unbounded => true,
+    };
+    let before_end = match range.end_bound() {
+        Bound::Included(end) => first <= *end,
+        Bound::Excluded(end) => first < *end,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+/// Index of a folder of dumped blocks (one .bin file per block, possibly in nested sub folders)
+///
+/// The index is cached on disk: opening it checks the modification time of every folder, only
+/// the folders modified since the last scan are listed again (and the skipped files checked
+/// again). The slots of a folder are loaded from the cache when a lookup needs them, so a
+/// lookup in a slot range only loads the folders covering this range (folders with overlapping
+/// slot ranges are loaded when the index is opened, to find the duplicates).
+pub struct BlockIndex {
+    root: PathBuf,
+    /// Sorted by first slot
+    folders: Vec<Folder>,
+    /// Sorted, with disjoint slot ranges
+    clusters: Vec<Cluster>,
+    skipped: Vec<(PathBuf, String)>,
+    duplicates: Vec<(Slot, PathBuf)>,
+}
+
+impl BlockIndex {
+    pub fn open(root: &Path) -> Result<Self, String> {
+        let cache_path = root
+            .join(INDEX_CACHE_FOLDER_NAME)
+            .join(INDEX_CACHE_FILE_NAME);
+        let mut cache = File::open(&cache_path)
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, IndexCache>(BufReader::new(file)).ok())
+            .filter(|cache| cache.version == INDEX_CACHE_VERSION)
+            .unwrap_or_default();
+
+        let mut folders = BTreeMap::new();
+        let mut rescanned = false;
+        let mut to_visit = vec![PathBuf::new()];
+        while let Some(relative_path) = to_visit.pop() {
+            let modified = modified(&root.join(&relative_path))?;
+            let cached = cache.folders.remove(&relative_path).and_then(|mut entry| {
+                if entry.modified != modified || is_racy(modified, entry.scanned_at) {
+                    return None;
+                }
+                // block list file unreadable: scan the folder again
+                let changed = entry.recheck(root, &relative_path).ok()?;
+                rescanned |= changed;
+                Some(entry)
+            });
+            let entry = match cached {
+                Some(entry) => entry,
+                None => {
+                    rescanned = true;
+                    scan_folder(root, &relative_path, modified)?
+                }
+            };
+            to_visit.extend(entry.subfolders.iter().cloned());
+            folders.insert(relative_path, entry);
+        }
+        // folders removed since the last scan
+        rescanned |= !cache.folders.is_empty();
+
+        let mut cache = IndexCache {
+            version: INDEX_CACHE_VERSION,
+            next_id: cache.next_id,
+            folders,
+        };
+        if rescanned {
+            if let Err(e) = save_cache(root, &mut cache) {
+                eprintln!(
+                    "Cannot write block index cache in {:?}: {} (the blocks will be listed again next time)",
+                    root, e
+                );
+            }
+        }
+
+        let mut index = BlockIndex {
+            root: root.to_path_buf(),
+            folders: Vec::with_capacity(cache.folders.len()),
+            clusters: Vec::new(),
+            skipped: Vec::new(),
+            duplicates: Vec::new(),
+        };
+        for (relative_path, entry) in cache.folders {
+            index.skipped.extend(
+                entry
+                    .skipped
+                    .iter()
+                    .map(|file| (relative_path.join(&file.file_name), file.reason.clone())),
+            );
+            let racy_slots: Vec<Slot> = entry
+                .racy_blocks
+                .iter()
+                .map(|block| Slot::new(block.slot.0, block.slot.1))
+                .collect();
+            let spans = entry
+                .listed_span
+                .into_iter()
+                .flat_map(|(first, last)| [first, last])
+                .map(|(period, thread)| Slot::new(period, thread))
+                .chain(racy_slots.iter().copied());
+            let (Some(first), Some(last)) = (spans.clone().min(), spans.max()) else {
+                continue;
+            };
+            let slots = match entry.listed {
+                Some(mut listed) => {
+                    listed.extend(racy_slots.iter().copied());
+                    listed.sort();
+                    OnceLock::from(listed)
+                }
+                None => OnceLock::new(),
+            };
+            index.folders.push(Folder {
+                path: relative_path,
+                modified: entry.modified,
+                blocks_file: entry.blocks_file.map(|id| blocks_file_path(root, id)),
+                racy_slots,
+                first,
+                last,
+                slots,
+            });
+        }
+        index.build_clusters();
+        index.remove_duplicates();
+        index.report();
+        Ok(index)
+    }
+
+    /// Sort the folders by first slot and group the ones with overlapping slot ranges
+    fn build_clusters(&mut self) {
+        self.folders
+            .sort_by(|a, b| a.first.cmp(&b.first).then_with(|| a.path.cmp(&b.path)));
+        self.clusters.clear();
+        for (folder_index, folder) in self.folders.iter().enumerate() {
+            match self.clusters.last_mut() {
+                Some(cluster) if folder.first <= cluster.last => {
+                    cluster.folders.end = folder_index + 1;
+                    cluster.last = cluster.last.max(folder.last);
+                }
+                _ => self.clusters.push(Cluster {
+                    folders: folder_index..folder_index + 1,
+                    first: folder.first,
+                    last: folder.last,
+                }),
+            }
+        }
+    }
+
+    /// Keep the block of the first folder (in path order) when several folders have the same slot
+    fn remove_duplicates(&mut self) {
+        let overlapping: Vec<Range<usize>> = self
+            .clusters
+            .iter()
+            .filter(|cluster| cluster.folders.len() > 1)
+            .map(|cluster| cluster.folders.clone())
+            .collect();
+        for folders in overlapping {
+            let mut by_path: Vec<usize> = folders.collect();
+            by_path.sort_by(|a, b| self.folders[*a].path.cmp(&self.folders[*b].path));
+            let mut seen = BTreeSet::new();
+            for folder_index in by_path {
+                let mut slots = self.folder_slots(&self.folders[folder_index]).to_vec();
+                let folder = &mut self.folders[folder_index];
+                slots.retain(|slot| {
+                    let first_seen = seen.insert(*slot);
+                    if !first_seen {
+                        self.duplicates.push((*slot, folder.path.clone()));
+                    }
+                    first_seen
+                });
+                folder.slots = OnceLock::from(slots);
+            }
+        }
+        self.duplicates.sort();
+    }
+
+    fn report(&self) {
+        if !self.skipped.is_empty() {
+            println!(
+                "{} files skipped in block folder {:?}:",
+                self.skipped.len(),
+                self.root
+            );
+            for (path, reason) in self.skipped.iter().take(MAX_SKIPPED_FILES_PRINTED) {
+                println!("  {:?}: {}", path, reason);
+            }
+            if self.skipped.len() > MAX_SKIPPED_FILES_PRINTED {
+                println!("  ...");
+            }
+        }
+        for (slot, folder) in self.duplicates.iter() {
+            println!(
+                "Duplicate block for slot {} in {:?} (ignored)",
+                slot,
+                self.root.join(folder)
+            );
+        }
+    }
+
+    /// Slots of the blocks of a folder, loaded from the cache (or listed again) on first use
+    fn folder_slots<'a>(&self, folder: &'a Folder) -> &'a [Slot] {
+        folder.slots.get_or_init(|| {
+            let listed = match folder.blocks_file.as_ref() {
+                Some(blocks_file) => read_blocks_file(blocks_file),
+                None => Err("no block list file".to_string()),
+            };
+            let mut slots = listed.unwrap_or_else(|e| {
+                println!(
+                    "Block index cache of {:?} unusable ({}), listing the folder again",
+                    self.root.join(&folder.path),
+                    e
+                );
+                match scan_folder(&self.root, &folder.path, folder.modified) {
+                    Ok(entry) => entry.listed.unwrap_or_default(),
+                    Err(e) => {
+                        eprintln!("Cannot list dumped blocks: {}", e);
+                        Vec::new()
+                    }
+                }
+            });
+            slots.extend(folder.racy_slots.iter().copied());
+            slots.sort();
+            slots.dedup();
+            slots
+        })
+    }
+
+    /// Slots of the cluster in the range, sorted
+    fn cluster_slots<R: RangeBounds<Slot>>(&self, cluster: &Cluster, range: &R) -> Vec<Slot> {
+        if !overlaps(range, cluster.first, cluster.last) {
+            return Vec::new();
+        }
+        let mut slots: Vec<Slot> = self.folders[cluster.folders.clone()]
+            .iter()
+            .filter(|folder| overlaps(range, folder.first, folder.last))
+            .flat_map(|folder| {
+                let folder_slots = self.folder_slots(folder);
+                let start = match range.start_bound() {
+                    Bound::Included(start) => folder_slots.partition_point(|s| s < start),
+                    Bound::Excluded(start) => folder_slots.partition_point(|s| s <= start),
+                    Bound::Unbounded => 0,
+                };
+                let end = match range.end_bound() {
+                    Bound::Included(end) => folder_slots.partition_point(|s| s <= end),
+                    Bound::Excluded(end) => folder_slots.partition_point(|s| s < end),
+                    Bound::Unbounded => folder_slots.len(),
+                };
+                folder_slots[start..end.max(start)].iter().copied()
+            })
+            .collect();
+        if cluster.folders.len() > 1 {
+            slots.sort();
+        }
+        slots
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path of the dumped block of the given slot (if any)
+    pub fn path(&self, slot: &Slot) -> Option<PathBuf> {
+        let cluster_index = self
+            .clusters
+            .partition_point(|cluster| cluster.last < *slot);
+        let cluster = self
+            .clusters
+            .get(cluster_index)
+            .filter(|cluster| cluster.first <= *slot)?;
+        self.folders[cluster.folders.clone()]
+            .iter()
+            .filter(|folder| folder.first <= *slot && *slot <= folder.last)
+            .find(|folder| self.folder_slots(folder).binary_search(slot).is_ok())
+            .map(|folder| self.root.join(&folder.path).join(block_file_name(slot)))
+    }
+
+    /// Register a block written at the root of the index
+    pub fn insert(&mut self, slot: Slot) {
+        if self.path(&slot).is_some() {
+            return;
+        }
+        let folder_index = match self
+            .folders
+            .iter()
+            .position(|f| f.path.as_os_str().is_empty())
+        {
+            Some(folder_index) => folder_index,
+            None => {
+                self.folders.push(Folder {
+                    path: PathBuf::new(),
+                    modified: (0, 0),
+                    blocks_file: None,
+                    racy_slots: Vec::new(),
+                    first: slot,
+                    last: slot,
+                    slots: OnceLock::from(Vec::new()),
+                });
+                self.folders.len() - 1
+            }
+        };
+        self.folder_slots(&self.folders[folder_index]);
+        let folder = &mut self.folders[folder_index];
+        let slots = folder.slots.get_mut().expect("folder slots loaded");
+        if let Err(position) = slots.binary_search(&slot) {
+            slots.insert(position, slot);
+        }
+        folder.first = folder.first.min(slot);
+        folder.last = folder.last.max(slot);
+        self.build_clusters();
+    }
+
+    pub fn first_slot(&self) -> Option<Slot> {
+        self.range(..).next()
+    }
+
+    pub fn last_slot(&self) -> Option<Slot> {
+        self.range(..).next_back()
+    }
+
+    /// Slots with a dumped block in the given range
+    pub fn range<'a, R: RangeBounds<Slot> + 'a>(
+        &'a self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = Slot> + 'a {
+        self.clusters
+            .iter()
+            .flat_map(move |cluster| self.cluster_slots(cluster, &range))
+    }
+
+    /// Files not matching the dumped block file name pattern (path, reason)
+    pub fn skipped(&self) -> &[(PathBuf, String)] {
+        &self.skipped
+    }
+
+    /// Blocks found more than once (slot, folder of the ignored copy)
+    pub fn duplicates(&self) -> &[(Slot, PathBuf)] {
+        &self.duplicates
+    }
+}
+
+/// Write the block list files of the folders scanned again, then the folder entries, and remove
+/// the block list files no longer used
+///
+/// Note: the cache folder is created at the root of the index, which changes the root folder
+/// modification time (the root entry is updated after a creation)
+fn save_cache(root: &Path, cache: &mut IndexCache) -> Result<(), String> {
+    let cache_folder = root.join(INDEX_CACHE_FOLDER_NAME);
+    if !cache_folder.exists() {
+        create_dir_all(&cache_folder)
+            .map_err(|e| format!("cannot create {:?}: {}", cache_folder, e))?;
+        let modified = modified(root)?;
+        if let Some(root_entry) = cache.folders.get_mut(Path::new("")) {
+            root_entry.modified = modified;
+        }
+    }
+
+    for entry in cache.folders.values_mut() {
+        if entry.blocks_file.is_some() {
+            continue;
+        }
+        let id = cache.next_id;
+        cache.next_id += 1;
+        write_blocks_file(
+            &blocks_file_path(root, id),
+            entry.listed.as_deref().unwrap_or_default(),
+        )?;
+        entry.blocks_file = Some(id);
+    }
+
+    let cache_path = cache_folder.join(INDEX_CACHE_FILE_NAME);
+    let file =
+        File::create(&cache_path).map_err(|e| format!("cannot create {:?}: {}", cache_path, e))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, cache)
+        .map_err(|e| e.to_string())
+        .and_then(|_| writer.flush().map_err(|e| e.to_string()))
+        .map_err(|e| format!("cannot write {:?}: {}", cache_path, e))?;
+
+    let used: BTreeSet<String> = cache
+        .folders
+        .values()
+        .filter_map(|entry| entry.blocks_file)
+        .map(|id| format!("{}.bin", id))
+        .collect();
+    let dir = read_dir(&cache_folder)
+        .map_err(|e| format!("cannot read folder {:?}: {}", cache_folder, e))?;
+    for dir_entry in dir.flatten() {
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        if file_name.ends_with(".bin") && !used.contains(&file_name) {
+            remove_file(dir_entry.path())
+                .map_err(|e| format!("cannot remove {:?}: {}", dir_entry.path(), e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_block(folder: &Path, slot: Slot) {
+        create_dir_all(folder).unwrap();
+        write(folder.join(block_file_name(&slot)), b"block").unwrap();
+    }
+
+    fn slots(index: &BlockIndex) -> Vec<Slot> {
+        index.range(..).collect()
+    }
+
+    #[test]
+    fn parse_stray_file_names() {
+        assert_eq!(
+            parse_block_file_name("block_slot_3_42.bin"),
+            Ok(Slot::new(42, 3))
+        );
+        for file_name in [
+            ".DS_Store",
+            "block_slot_1.bin",
+            "block_slot_1_2.bin.tmp",
+            "block_slot_a_2.bin",
+            "block_slot_1_b.bin",
+            "block_slot_-1_2.bin",
+        ] {
+            assert!(
+                parse_block_file_name(file_name).is_err(),
+                "{} must be skipped",
+                file_name
+            );
+        }
+        let invalid_thread = format!("block_slot_{}_2.bin", THREAD_COUNT);
+        assert!(parse_block_file_name(&invalid_thread).is_err());
+    }
+
+    #[test]
+    fn nested_folders_and_stray_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_block(root, Slot::new(1, 0));
+        write_block(&root.join("a"), Slot::new(2, 1));
+        write_block(&root.join("a").join("b"), Slot::new(3, 2));
+        write(root.join(".DS_Store"), b"x").unwrap();
+        write(root.join("a").join("block_slot_1.bin"), b"x").unwrap();
+        write(root.join(block_file_name(&Slot::new(4, 0))), b"").unwrap();
+
+        let expected = vec![Slot::new(1, 0), Slot::new(2, 1), Slot::new(3, 2)];
+        let index = BlockIndex::open(root).unwrap();
+        assert_eq!(slots(&index), expected);
+        assert_eq!(index.skipped().len(), 3);
+        assert_eq!(
+            index.path(&Slot::new(3, 2)),
+            Some(root.join("a").join("b").join("block_slot_2_3.bin"))
+        );
+        assert_eq!(index.path(&Slot::new(4, 0)), None);
+        assert_eq!(
+            index.range(Slot::new(2, 0)..).collect::<Vec<_>>(),
+            expected[1..]
+        );
+
+        // from the cache
+        let index = BlockIndex::open(root).unwrap();
+        assert_eq!(slots(&index), expected);
+        assert_eq!(index.first_slot(), Some(Slot::new(1, 0)));
+        assert_eq!(index.last_slot(), Some(Slot::new(3, 2)));
+        assert_eq!(index.skipped().len(), 3);
+    }
+
+    #[test]
+    fn duplicates_keep_the_first_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_block(&root.join("a"), Slot::new(1, 0));
+        write_block(&root.join("a"), Slot::new(3, 0));
+        write_block(&root.join("b"), Slot::new(2, 0));
+        write_block(&root.join("b"), Slot::new(3, 0));
+
+        let index = BlockIndex::open(root).unwrap();
+        assert_eq!(
+            slots(&index),
+            vec![Slot::new(1, 0), Slot::new(2, 0), Slot::new(3, 0)]
+        );
+        assert_eq!(index.duplicates(), &[(Slot::new(3, 0), PathBuf::from("b"))]);
+        assert_eq!(
+            index.path(&Slot::new(3, 0)),
+            Some(root.join("a").join("block_slot_0_3.bin"))
+        );
+    }
+
+    fn set_modified_long_ago(path: &Path) {
+        File::open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - std::time::Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[test]
+    fn folders_loaded_from_the_cache_when_needed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_block(&root.join("a"), Slot::new(1, 0));
+        write_block(&root.join("a"), Slot::new(1, 1));
+        write_block(&root.join("b"), Slot::new(2, 0));
+        for slot in [Slot::new(1, 0), Slot::new(1, 1)] {
+            set_modified_long_ago(&root.join("a").join(block_file_name(&slot)));
+        }
+        set_modified_long_ago(&root.join("b").join(block_file_name(&Slot::new(2, 0))));
+        BlockIndex::open(root).unwrap();
+        for folder in [root.join("a"), root.join("b"), root.to_path_buf()] {
+            set_modified_long_ago(&folder);
+        }
+        // scanned again (folders modified), block lists saved
+        BlockIndex::open(root).unwrap();
+
+        let index = BlockIndex::open(root).unwrap();
+        assert!(index
+            .folders
+            .iter()
+            .all(|folder| folder.slots.get().is_none()));
+        assert_eq!(
+            index.range(Slot::new(2, 0)..).collect::<Vec<_>>(),
+            vec![Slot::new(2, 0)]
+        );
+        let loaded: Vec<_> = index
+            .folders
+            .iter()
+            .filter(|folder| folder.slots.get().is_some())
+            .map(|folder| folder.path.clone())
+            .collect();
+        assert_eq!(loaded, vec![PathBuf::from("b")]);
+        assert_eq!(
+            slots(&index),
+            vec![Slot::new(1, 0), Slot::new(1, 1), Slot::new(2, 0)]
+        );
+    }
+
+    #[test]
+    fn inserted_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = BlockIndex::open(dir.path()).unwrap();
+        assert_eq!(index.first_slot(), None);
+        for slot in [Slot::new(5, 1), Slot::new(2, 0)] {
+            write_block(dir.path(), slot);
+            index.insert(slot);
+        }
+        assert_eq!(slots(&index), vec![Slot::new(2, 0), Slot::new(5, 1)]);
+        assert!(index.path(&Slot::new(5, 1)).is_some());
+    }
+
+    #[test]
+    fn racy_modification_time() {
+        assert!(is_racy((100, 0), (100, 500)));
+        assert!(is_racy((100, 0), (101, 0)));
+        assert!(!is_racy((100, 0), (102, 0)));
+    }
+
+    #[test]
+    fn recheck_racy_and_skipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write_block(root, Slot::new(1, 0));
+        // partially written
+        write(root.join(block_file_name(&Slot::new(2, 0))), b"").unwrap();
+
+        let mut entry = scan_folder(root, Path::new(""), modified(root).unwrap()).unwrap();
+        // just written: racy
+        assert_eq!(entry.racy_blocks.len(), 1);
+        assert_eq!(entry.skipped.len(), 1);
+
+        // modified after the scan, in the same second
+        write(root.join(block_file_name(&Slot::new(1, 0))), b"").unwrap();
+        write(root.join(block_file_name(&Slot::new(2, 0))), b"block").unwrap();
+        assert!(entry.recheck(root, Path::new("")).unwrap());
+        let racy: Vec<_> = entry.racy_blocks.iter().map(|block| block.slot).collect();
+        assert_eq!(racy, vec![(2, 0)]);
+        assert_eq!(entry.skipped.len(), 1);
+        assert_eq!(
+            entry.skipped[0].file_name,
+            block_file_name(&Slot::new(1, 0))
+        );
+
+        // last modified long before the next check: listed
+        File::options()
+            .write(true)
+            .open(root.join(block_file_name(&Slot::new(2, 0))))
+            .unwrap()
+            .set_modified(SystemTime::now() - std::time::Duration::from_secs(60))
+            .unwrap();
+        assert!(entry.recheck(root, Path::new("")).unwrap());
+        assert!(entry.racy_blocks.is_empty());
+        let mut listed = entry.listed.clone().unwrap();
+        listed.sort();
+        assert_eq!(listed, vec![Slot::new(2, 0)]);
+        assert_eq!(entry.blocks_file, None);
+
+        // only the unchanged empty file is checked again
+        assert!(!entry.recheck(root, Path::new("")).unwrap());
+        assert_eq!(entry.skipped.len(), 1);
+    }
+}
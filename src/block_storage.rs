@@ -0,0 +1,301 @@
+use std::fs::{create_dir_all, read, read_dir, write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use massa_models::config::THREAD_COUNT;
+use massa_models::slot::{Slot, SLOT_KEY_SIZE};
+use massa_proto_rs::massa::model::v1::{self as grpc_model};
+use prost::Message;
+use rocksdb::{Direction, IteratorMode, DB};
+
+use crate::block_index::{block_file_name, BlockIndex};
+use crate::grpc_conv::{ConversionError, DumpedBlock};
+
+/// Layout of the dumped blocks folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BlocksFormat {
+    /// One .bin file per block (file_storage_backend of the node)
+    File,
+    /// A RocksDB database (db_storage_backend of the node)
+    Rocksdb,
+    /// Detect the format from the folder content
+    Auto,
+}
+
+impl BlocksFormat {
+    /// Resolve `Auto` by looking at the folder content (a RocksDB `CURRENT` file,
+    /// or .bin files / sub folders of .bin files)
+    pub fn resolve(self, path: &Path) -> Result<BlocksFormat, String> {
+        if self != BlocksFormat::Auto {
+            return Ok(self);
+        }
+        if path.join("CURRENT").is_file() {
+            return Ok(BlocksFormat::Rocksdb);
+        }
+        let has_bin_files = read_dir(path)
+            .map_err(|e| format!("cannot read block folder {:?}: {}", path, e))?
+            .filter_map(|entry| entry.ok())
+            .any(|entry| {
+                entry.path().is_dir() || entry.path().extension().map_or(false, |ext| ext == "bin")
+            });
+        if has_bin_files {
+            Ok(BlocksFormat::File)
+        } else {
+            Err(format!(
+                "cannot detect the block format of {:?} (no RocksDB CURRENT file nor .bin files), use --blocks_format",
+                path
+            ))
+        }
+    }
+}
+
+/// What to do with a dumped block that cannot be decoded or converted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BadBlockPolicy {
+    /// Log the error and replay the slot as a missed slot
+    Skip,
+    /// Stop the replay
+    Abort,
+}
+
+/// Dumped blocks, in one of the layouts written by the node
+pub enum BlockStorage {
+    File(BlockIndex),
+    Rocksdb(DB),
+}
+
+impl BlockStorage {
+    /// Open an existing dumped blocks folder to read it (format must be resolved, see `BlocksFormat::resolve`)
+    ///
+    /// Note: a RocksDB is opened read only (without its lock), the node can keep writing to it
+    pub fn open(path: &Path, format: BlocksFormat) -> Result<Self, String> {
+        match format {
+            BlocksFormat::File => BlockIndex::open(path).map(BlockStorage::File),
+            BlocksFormat::Rocksdb => {
+                let opts = rocksdb::Options::default();
+                DB::open_for_read_only(&opts, path, true)
+                    .map(BlockStorage::Rocksdb)
+                    .map_err(|e| format!("cannot open block db {:?}: {}", path, e))
+            }
+            BlocksFormat::Auto => unreachable!("block format must be resolved before opening"),
+        }
+    }
+
+    /// Open (or create) a dumped blocks folder to write blocks into it
+    pub fn create(path: &Path, format: BlocksFormat) -> Result<Self, String> {
+        match format {
+            BlocksFormat::File => {
+                create_dir_all(path)
+                    .map_err(|e| format!("cannot create block folder {:?}: {}", path, e))?;
+                BlockIndex::open(path).map(BlockStorage::File)
+            }
+            BlocksFormat::Rocksdb => {
+                let mut opts = rocksdb::Options::default();
+                opts.create_if_missing(true);
+                DB::open(&opts, path)
+                    .map(BlockStorage::Rocksdb)
+                    .map_err(|e| format!("cannot open block db {:?}: {}", path, e))
+            }
+            BlocksFormat::Auto => unreachable!("block format must be resolved before opening"),
+        }
+    }
+
+    /// Content of the dumped block of the given slot (None if there is none, error if it cannot be read)
+    pub fn read(&self, slot: &Slot) -> Option<Result<Vec<u8>, String>> {
+        match self {
+            BlockStorage::File(index) => index.path(slot).map(|path| {
+                read(&path).map_err(|e| format!("cannot read dumped block {:?}: {}", path, e))
+            }),
+            BlockStorage::Rocksdb(db) => db
+                .get(slot.to_bytes_key())
+                .map_err(|e| format!("cannot read block db: {}", e))
+                .transpose(),
+        }
+    }
+
+    /// Read, decode and convert the dumped block of the given slot (None if there is none)
+    pub fn read_block(&self, slot: &Slot) -> Option<Result<DumpedBlock, ConversionError>> {
+        let content = match self.read(slot)? {
+            Ok(content) => content,
+            Err(e) => return Some(Err(ConversionError::new("block", e).at_slot(*slot))),
+        };
+        Some(
+            grpc_model::FilledBlock::decode(&content[..])
+                .map_err(|e| {
+                    ConversionError::new("block", format!("cannot decode block: {}", e))
+                        .at_slot(*slot)
+                })
+                .and_then(DumpedBlock::try_from),
+        )
+    }
+
+    pub fn write(&mut self, slot: &Slot, content: &[u8]) {
+        match self {
+            BlockStorage::File(index) => {
+                let path = index.root().join(block_file_name(slot));
+                write(&path, content)
+                    .unwrap_or_else(|e| panic!("Failed to write block file {:?}: {}", path, e));
+                index.insert(*slot);
+            }
+            BlockStorage::Rocksdb(db) => db
+                .put(slot.to_bytes_key(), content)
+                .unwrap_or_else(|e| panic!("Failed to write block db: {}", e)),
+        }
+    }
+
+    pub fn first_slot(&self) -> Option<Slot> {
+        match self {
+            BlockStorage::File(index) => index.first_slot(),
+            BlockStorage::Rocksdb(db) => db_slots(db, IteratorMode::Start).next(),
+        }
+    }
+
+    pub fn last_slot(&self) -> Option<Slot> {
+        match self {
+            BlockStorage::File(index) => index.last_slot(),
+            BlockStorage::Rocksdb(db) => db_slots(db, IteratorMode::End).next(),
+        }
+    }
+
+    /// Period of the last dumped block of every thread (None if a thread has no block)
+    pub fn last_periods(&self) -> Vec<Option<u64>> {
+        let slots_from_end: Box<dyn Iterator<Item = Slot> + '_> = match self {
+            BlockStorage::File(index) => Box::new(index.range(..).rev()),
+            BlockStorage::Rocksdb(db) => Box::new(db_slots(db, IteratorMode::End)),
+        };
+        let mut last_periods = vec![None; THREAD_COUNT as usize];
+        let mut missing = last_periods.len();
+        for slot in slots_from_end {
+            let last_period = &mut last_periods[slot.thread as usize];
+            if last_period.is_none() {
+                *last_period = Some(slot.period);
+                missing -= 1;
+                if missing == 0 {
+                    break;
+                }
+            }
+        }
+        last_periods
+    }
+
+    /// Dumped block index (file layout only)
+    pub fn index(&self) -> Option<&BlockIndex> {
+        match self {
+            BlockStorage::File(index) => Some(index),
+            BlockStorage::Rocksdb(_) => None,
+        }
+    }
+
+    /// Slots with a dumped block, from `from_slot` to `until_slot` (included)
+    pub fn slots(&self, from_slot: Option<Slot>, until_slot: Option<Slot>) -> Vec<Slot> {
+        let until = |slot: &Slot| until_slot.map_or(true, |until_slot| *slot <= until_slot);
+        match (self, from_slot) {
+            (BlockStorage::File(index), Some(from_slot)) => {
+                index.range(from_slot..).take_while(until).collect()
+            }
+            (BlockStorage::File(index), None) => index.range(..).take_while(until).collect(),
+            (BlockStorage::Rocksdb(db), Some(from_slot)) => {
+                let from_key = from_slot.to_bytes_key();
+                db_slots(db, IteratorMode::From(&from_key, Direction::Forward))
+                    .take_while(until)
+                    .collect()
+            }
+            (BlockStorage::Rocksdb(db), None) => db_slots(db, IteratorMode::Start)
+                .take_while(until)
+                .collect(),
+        }
+    }
+}
+
+/// Consecutive slots without a dumped block
+pub struct Gap {
+    pub first: Slot,
+    pub last: Slot,
+    pub count: u64,
+}
+
+/// Last slot a replay can finalize, given the period of the last dumped block of every thread
+/// (see `BlockStorage::last_periods`)
+///
+/// A missed slot is finalized by feeding a later block of its thread, so the replay stops before
+/// the first slot with no block at or after it in its thread (None if a thread has no block)
+pub fn replay_end(last_periods: &[Option<u64>]) -> Option<Slot> {
+    let first_unfinalized = last_periods
+        .iter()
+        .enumerate()
+        .map(|(thread, last_period)| last_period.map(|p| Slot::new(p + 1, thread as u8)))
+        .collect::<Option<Vec<Slot>>>()?
+        .into_iter()
+        .min()?;
+    first_unfinalized.get_prev_slot(THREAD_COUNT).ok()
+}
+
+/// Position of a slot in the chain (number of slots since genesis)
+pub fn slot_index(slot: &Slot) -> u64 {
+    slot.period * THREAD_COUNT as u64 + slot.thread as u64
+}
+
+fn slot_from_index(index: u64) -> Slot {
+    Slot::new(
+        index / THREAD_COUNT as u64,
+        (index % THREAD_COUNT as u64) as u8,
+    )
+}
+
+/// Slots missing between the given (sorted) slots
+pub fn find_gaps(slots: &[Slot]) -> Vec<Gap> {
+    slots
+        .windows(2)
+        .filter_map(|pair| {
+            let (a, b) = (slot_index(&pair[0]), slot_index(&pair[1]));
+            (b > a + 1).then(|| Gap {
+                first: slot_from_index(a + 1),
+                last: slot_from_index(b - 1),
+                count: b - a - 1,
+            })
+        })
+        .collect()
+}
+
+fn db_slots<'a>(db: &'a DB, mode: IteratorMode) -> impl Iterator<Item = Slot> + 'a {
+    db.iterator(mode).map(|x| match x {
+        Ok((k, _v)) => {
+            let buffer: &[u8; SLOT_KEY_SIZE] = &k.to_vec().try_into().unwrap();
+            Slot::from_bytes_key(buffer)
+        }
+        Err(e) => panic!("{}", e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_end_stops_before_the_first_thread_without_later_block() {
+        let mut last_periods = vec![Some(10); THREAD_COUNT as usize];
+        assert_eq!(
+            replay_end(&last_periods),
+            Some(Slot::new(10, THREAD_COUNT - 1))
+        );
+
+        last_periods[3] = Some(7);
+        assert_eq!(replay_end(&last_periods), Some(Slot::new(8, 2)));
+
+        last_periods[0] = Some(5);
+        assert_eq!(
+            replay_end(&last_periods),
+            Some(Slot::new(5, THREAD_COUNT - 1))
+        );
+    }
+
+    #[test]
+    fn replay_end_with_a_thread_without_block() {
+        for thread in [0, 5, THREAD_COUNT as usize - 1] {
+            let mut last_periods = vec![Some(10); THREAD_COUNT as usize];
+            last_periods[thread] = None;
+            assert_eq!(replay_end(&last_periods), None);
+        }
+        assert_eq!(replay_end(&[]), None);
+    }
+}
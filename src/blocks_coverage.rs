@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use massa_models::config::THREAD_COUNT;
+use massa_models::slot::Slot;
+
+use crate::args::BlocksCoverageArgs;
+use crate::block_storage::{find_gaps, replay_end, slot_index, BlockStorage, Gap};
+use crate::snapshot::{list_snapshots, read_snapshot, Snapshot};
+
+/// Max number of gaps printed (the largest ones)
+const MAX_GAPS_PRINTED: usize = 20;
+
+/// Show which slots have a dumped block, and from which backups a replay can run without gaps
+pub fn blocks_coverage(db_path: Option<&Path>, args: &BlocksCoverageArgs) {
+    let format = args
+        .blocks_format
+        .resolve(&args.dump_block_path)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let block_db = BlockStorage::open(&args.dump_block_path, format).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let slots = block_db.slots(None, None);
+    let (Some(first_slot), Some(last_slot)) = (slots.first().copied(), slots.last().copied())
+    else {
+        println!("No dumped block in {:?}", args.dump_block_path);
+        return;
+    };
+
+    println!(
+        "{} blocks ({:?}) from slot {} to slot {}",
+        slots.len(),
+        format,
+        first_slot,
+        last_slot
+    );
+
+    let end = replay_end(&block_db.last_periods());
+    match end {
+        Some(end) => println!(
+            "A replay cannot go past slot {} (no later block in thread {})",
+            end,
+            (end.thread + 1) % THREAD_COUNT
+        ),
+        None => println!("A replay cannot finalize any slot: a thread has no block"),
+    }
+
+    println!("Per thread:");
+    for thread in 0..THREAD_COUNT {
+        let periods: Vec<u64> = slots
+            .iter()
+            .filter(|s| s.thread == thread)
+            .map(|s| s.period)
+            .collect();
+        match (periods.first(), periods.last()) {
+            (Some(first), Some(last)) => println!(
+                "  thread {:>2}: periods {} to {}, {} blocks, {} missing",
+                thread,
+                first,
+                last,
+                periods.len(),
+                last - first + 1 - periods.len() as u64
+            ),
+            _ => println!("  thread {:>2}: no block", thread),
+        }
+    }
+
+    let gaps = find_gaps(&slots);
+    println!(
+        "{} slots without block (missed slots or missing dumps) in {} gaps",
+        gaps.iter().map(|gap| gap.count).sum::<u64>(),
+        gaps.len()
+    );
+    let mut largest_gaps: Vec<&Gap> = gaps.iter().collect();
+    largest_gaps.sort_by_key(|gap| std::cmp::Reverse(gap.count));
+    largest_gaps.truncate(MAX_GAPS_PRINTED);
+    largest_gaps.sort_by_key(|gap| gap.first);
+    for gap in largest_gaps.iter() {
+        println!(
+            "  slot {} to slot {} ({} slots)",
+            gap.first, gap.last, gap.count
+        );
+    }
+    if gaps.len() > MAX_GAPS_PRINTED {
+        println!("  ... ({} largest gaps shown)", MAX_GAPS_PRINTED);
+    }
+
+    if let Some(index) = block_db.index() {
+        for (slot, folder) in index.duplicates() {
+            println!(
+                "Duplicate block for slot {} in {:?}",
+                slot,
+                index.root().join(folder)
+            );
+        }
+        for (path, reason) in index.skipped() {
+            println!("Skipped file {:?}: {}", path, reason);
+        }
+    }
+
+    let snapshots: Vec<Snapshot> = match (&args.db_backup_path, db_path) {
+        (Some(backup), _) => vec![read_snapshot(backup.clone())],
+        (None, Some(db_path)) => list_snapshots(db_path),
+        (None, None) => Vec::new(),
+    };
+    if snapshots.is_empty() {
+        println!("No backup to check (use --path or --backup)");
+        return;
+    }
+
+    // A gap longer than max_gap is considered as missing dumps (not missed slots)
+    let holes: Vec<&Gap> = gaps.iter().filter(|gap| gap.count > args.max_gap).collect();
+    println!(
+        "Backups (gaps of more than {} slots are missing dumps):",
+        args.max_gap
+    );
+    for snapshot in snapshots.iter() {
+        println!(
+            "  {:?} (slot {}): {}",
+            snapshot.path,
+            snapshot.slot,
+            replay_coverage(snapshot.slot, end, first_slot, &holes, args.max_gap)
+        );
+    }
+}
+
+/// How far a replay starting at the end of `start_slot` can go (`end`: see `replay_end`),
+/// and the missing dumps on the way (replayed as missed slots)
+fn replay_coverage(
+    start_slot: Slot,
+    end: Option<Slot>,
+    first_slot: Slot,
+    holes: &[&Gap],
+    max_gap: u64,
+) -> String {
+    let Some(end) = end.filter(|end| *end > start_slot) else {
+        return "no dumped block can finalize the slot after the backup".to_string();
+    };
+    let mut coverage = format!("can replay until slot {}", end);
+
+    let start = slot_index(&start_slot) + 1;
+    if slot_index(&first_slot) > start + max_gap {
+        coverage.push_str(&format!(
+            ", but dumped blocks start late (slot {})",
+            first_slot
+        ));
+    }
+    let holes: Vec<&&Gap> = holes
+        .iter()
+        .filter(|hole| slot_index(&hole.last) >= start && hole.first <= end)
+        .collect();
+    if let Some(hole) = holes.first() {
+        coverage.push_str(&format!(
+            ", but {} gaps replayed as missed slots (first: slot {} to slot {}, {} slots)",
+            holes.len(),
+            hole.first,
+            hole.last,
+            hole.count
+        ));
+    }
+    coverage
+}
@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::ValueEnum;
+use massa_models::slot::Slot;
+use serde_json::Value;
+
+use crate::args::CompareGasArgs;
+use crate::config::{check_gas_costs_folder, config_args};
+
+/// Execution of an operation in one replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OperationResult {
+    success: bool,
+    /// Data of the error events of the operation
+    errors: Vec<String>,
+}
+
+/// Execution of a slot in one replay
+struct SlotResult {
+    block_id: Option<String>,
+    operations: BTreeMap<String, OperationResult>,
+    /// Gas used by the smart contract operations (None: not measured)
+    gas_used: BTreeMap<String, Option<u64>>,
+    event_count: usize,
+    state_changes: Value,
+}
+
+impl SlotResult {
+    fn from_json(output: &Value) -> Self {
+        let mut errors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let events = output["events"].as_array().cloned().unwrap_or_default();
+        for event in events.iter() {
+            let context = &event["context"];
+            if let (Some(op_id), Some(true)) = (
+                context["origin_operation_id"].as_str(),
+                context["is_error"].as_bool(),
+            ) {
+                errors
+                    .entry(op_id.to_string())
+                    .or_default()
+                    .push(event["data"].as_str().unwrap_or_default().to_string());
+            }
+        }
+
+        let operations = output["state_changes"]["executed_ops"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|op| {
+                let op_id = op["operation_id"].as_str()?.to_string();
+                let result = OperationResult {
+                    success: op["success"].as_bool().unwrap_or(false),
+                    errors: errors.remove(&op_id).unwrap_or_default(),
+                };
+                Some((op_id, result))
+            })
+            .collect();
+
+        let gas_used = output["gas_used"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(op_id, gas)| (op_id.clone(), gas.as_u64()))
+            .collect();
+
+        SlotResult {
+            block_id: output["block_id"].as_str().map(|id| id.to_string()),
+            operations,
+            gas_used,
+            event_count: events.len(),
+            state_changes: output["state_changes"].clone(),
+        }
+    }
+
+    fn failed_count(&self) -> usize {
+        self.operations.values().filter(|op| !op.success).count()
+    }
+}
+
+/// Read the execution outputs written by `replay --output`
+fn read_outputs(path: &Path) -> Result<BTreeMap<Slot, SlotResult>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {:?}: {}", path, e))?;
+    let mut outputs = BTreeMap::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Cannot read {:?}: {}", path, e))?;
+        let output: Value =
+            serde_json::from_str(&line).map_err(|e| format!("{:?} line {}: {}", path, i + 1, e))?;
+        let (Some(period), Some(thread)) = (
+            output["slot"]["period"].as_u64(),
+            output["slot"]["thread"].as_u64(),
+        ) else {
+            return Err(format!("{:?} line {}: no slot", path, i + 1));
+        };
+        outputs.insert(
+            Slot::new(period, thread as u8),
+            SlotResult::from_json(&output),
+        );
+    }
+    Ok(outputs)
+}
+
+/// Run a replay with the given gas costs (in a child process, so each replay starts from a clean state)
+fn run_replay(
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    args: &CompareGasArgs,
+    gas_costs: &Path,
+    output: &Path,
+) -> bool {
+    let exe = std::env::current_exe().expect("Cannot find current executable");
+    let mut command = Command::new(exe);
+    command
+        .arg("--path")
+        .arg(db_path)
+        .arg("--initial_roll_path")
+        .arg(initial_rolls_path)
+        .args(config_args())
+        .arg("replay")
+        .arg("--blocks")
+        .arg(&args.dump_block_path)
+        .arg("--blocks_format")
+        .arg(args.blocks_format.to_possible_value().unwrap().get_name())
+        .arg("--backup")
+        .arg(&args.db_backup_path)
+        .arg("--execution_timeout")
+        .arg(args.execution_timeout.to_string())
+        .arg("--gas_costs")
+        .arg(gas_costs)
+        .arg("--output")
+        .arg(output)
+        .arg("--measure_gas");
+    if let Some((period, thread)) = args.until_slot {
+        command
+            .arg("--until_slot")
+            .arg(format!("{},{}", period, thread));
+    }
+
+    println!("Replaying with gas costs {:?}...", gas_costs);
+    let status = command.status().expect("Failed to run replay");
+    if !status.success() {
+        eprintln!("Replay failed (exit code: {:?})", status.code());
+    }
+    status.success()
+}
+
+fn describe(result: Option<&OperationResult>) -> String {
+    match result {
+        None => "not executed".to_string(),
+        Some(result) if result.success => "success".to_string(),
+        Some(result) => format!("failure ({})", result.errors.join(" | ")),
+    }
+}
+
+fn describe_gas(gas: Option<u64>) -> String {
+    gas.map_or("not measured".to_string(), |gas| gas.to_string())
+}
+
+/// Gas used by the operations measured in both replays, summed (A, B)
+fn block_gas(a: &SlotResult, b: &SlotResult) -> (u64, u64) {
+    a.gas_used
+        .iter()
+        .filter_map(|(op_id, gas_a)| Some(((*gas_a)?, (*b.gas_used.get(op_id)?)?)))
+        .fold((0, 0), |(sum_a, sum_b), (gas_a, gas_b)| {
+            (sum_a + gas_a, sum_b + gas_b)
+        })
+}
+
+/// Signed difference B - A
+fn delta(a: u64, b: u64) -> String {
+    format!("{:+}", b as i128 - a as i128)
+}
+
+/// Replay the same blocks with two gas cost tables, and report the operations whose
+/// execution (success / failure, errors) or gas used changed, and the blocks whose state
+/// changes or gas used differ
+///
+/// Note: the gas used is measured by the replays (see --measure_gas)
+pub fn compare_gas(db_path: &Path, initial_rolls_path: &Path, args: &CompareGasArgs) {
+    for gas_costs in [&args.gas_costs_a, &args.gas_costs_b] {
+        check_gas_costs_folder(gas_costs).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    // keep the temp dir alive until the end of the comparison
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let output_dir: PathBuf = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| temp_dir.path().to_path_buf());
+    std::fs::create_dir_all(&output_dir).expect("Failed to create output dir");
+
+    let mut outputs = Vec::new();
+    for (gas_costs, file_name) in [
+        (&args.gas_costs_a, "outputs_a.ndjson"),
+        (&args.gas_costs_b, "outputs_b.ndjson"),
+    ] {
+        let output = output_dir.join(file_name);
+        // the replay appends to its output file
+        let _ = std::fs::remove_file(&output);
+        if !run_replay(db_path, initial_rolls_path, args, gas_costs, &output) {
+            std::process::exit(1);
+        }
+        outputs.push(read_outputs(&output).unwrap_or_else(|e| {
+            eprintln!("Cannot read execution outputs: {}", e);
+            std::process::exit(1);
+        }));
+    }
+    let (outputs_b, outputs_a) = (outputs.pop().unwrap(), outputs.pop().unwrap());
+
+    let mut compared_operations = 0;
+    let mut changed_operations = 0;
+    let mut changed_blocks = 0;
+    let mut changed_gas_operations = 0;
+    let (mut total_gas_a, mut total_gas_b) = (0, 0);
+    let slots: BTreeSet<&Slot> = outputs_a.keys().chain(outputs_b.keys()).collect();
+    for slot in slots {
+        let (Some(a), Some(b)) = (outputs_a.get(slot), outputs_b.get(slot)) else {
+            println!("Slot {}: only executed in one replay", slot);
+            continue;
+        };
+
+        let op_ids: BTreeSet<&String> = a.operations.keys().chain(b.operations.keys()).collect();
+        compared_operations += op_ids.len();
+        let mut changes = Vec::new();
+        for op_id in op_ids {
+            let (op_a, op_b) = (a.operations.get(op_id), b.operations.get(op_id));
+            if op_a != op_b {
+                changes.push(format!(
+                    "  operation {}: {} -> {}",
+                    op_id,
+                    describe(op_a),
+                    describe(op_b)
+                ));
+            }
+        }
+        changed_operations += changes.len();
+
+        let gas_op_ids: BTreeSet<&String> = a.gas_used.keys().chain(b.gas_used.keys()).collect();
+        let mut gas_changes = Vec::new();
+        for op_id in gas_op_ids {
+            let gas_a = a.gas_used.get(op_id).copied().flatten();
+            let gas_b = b.gas_used.get(op_id).copied().flatten();
+            if gas_a == gas_b {
+                continue;
+            }
+            gas_changes.push(match (gas_a, gas_b) {
+                (Some(gas_a), Some(gas_b)) => format!(
+                    "  operation {}: gas used {} -> {} ({})",
+                    op_id,
+                    gas_a,
+                    gas_b,
+                    delta(gas_a, gas_b)
+                ),
+                _ => format!(
+                    "  operation {}: gas used {} -> {}",
+                    op_id,
+                    describe_gas(gas_a),
+                    describe_gas(gas_b)
+                ),
+            });
+        }
+        changed_gas_operations += gas_changes.len();
+        let (gas_a, gas_b) = block_gas(a, b);
+        total_gas_a += gas_a;
+        total_gas_b += gas_b;
+
+        if changes.is_empty() && gas_changes.is_empty() && a.state_changes == b.state_changes {
+            continue;
+        }
+        changed_blocks += 1;
+        println!(
+            "Slot {} (block {}): failed operations {} -> {}, events {} -> {}, gas used {} -> {} ({}){}",
+            slot,
+            a.block_id.as_deref().unwrap_or("none"),
+            a.failed_count(),
+            b.failed_count(),
+            a.event_count,
+            b.event_count,
+            gas_a,
+            gas_b,
+            delta(gas_a, gas_b),
+            if a.state_changes == b.state_changes {
+                ""
+            } else {
+                ", state changes differ"
+            }
+        );
+        for change in changes.into_iter().chain(gas_changes) {
+            println!("{}", change);
+        }
+    }
+
+    println!(
+        "{} operations compared: {} changed, {} with a different gas used, {} slots with a different execution",
+        compared_operations, changed_operations, changed_gas_operations, changed_blocks
+    );
+    println!(
+        "Gas used (operations measured in both replays): {} -> {} ({})",
+        total_gas_a,
+        total_gas_b,
+        delta(total_gas_a, total_gas_b)
+    );
+    if args.output_dir.is_some() {
+        println!("Execution outputs kept in {:?}", output_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn slot_result(gas_used: Value) -> SlotResult {
+        SlotResult::from_json(&json!({
+            "slot": { "period": 1, "thread": 0 },
+            "block_id": "B1",
+            "events": [],
+            "state_changes": { "executed_ops": [] },
+            "gas_used": gas_used,
+        }))
+    }
+
+    #[test]
+    fn gas_used_of_the_output() {
+        let result = slot_result(json!({ "O1": 1200, "O2": null }));
+        assert_eq!(result.gas_used.get("O1"), Some(&Some(1200)));
+        assert_eq!(result.gas_used.get("O2"), Some(&None));
+        assert!(slot_result(Value::Null).gas_used.is_empty());
+    }
+
+    #[test]
+    fn block_gas_of_the_operations_measured_in_both_replays() {
+        let a = slot_result(json!({ "O1": 1000, "O2": 500, "O3": null, "O4": 70 }));
+        let b = slot_result(json!({ "O1": 1500, "O2": 400, "O3": 300 }));
+        assert_eq!(block_gas(&a, &b), (1500, 1900));
+        assert_eq!(delta(1500, 1900), "+400");
+        assert_eq!(delta(500, 400), "-100");
+        assert_eq!(delta(7, 7), "+0");
+    }
+}
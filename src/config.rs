@@ -1,6 +1,9 @@
 // std lib
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
 
+use clap::ValueEnum;
 use massa_async_pool::AsyncPoolConfig;
 use massa_db_exports::MassaDBConfig;
 use massa_executed_ops::{ExecutedDenunciationsConfig, ExecutedOpsConfig};
@@ -8,6 +11,7 @@ use massa_execution_exports::{ExecutionConfig, GasCosts, StorageCostsConstants};
 use massa_final_state::FinalStateConfig;
 use massa_ledger_exports::LedgerConfig;
 use massa_models::address::Address;
+use massa_models::amount::Amount;
 use massa_models::config::{
     ASYNC_MSG_CST_GAS_COST, BASE_OPERATION_GAS_COST, BLOCK_REWARD, CHAINID, CHANNEL_SIZE,
     DENUNCIATION_EXPIRE_PERIODS, ENDORSEMENT_COUNT, GENESIS_KEY, GENESIS_TIMESTAMP,
@@ -23,14 +27,381 @@ use massa_models::config::{
     ROLL_PRICE, SELECTOR_DRAW_CACHE_SIZE, T0, THREAD_COUNT,
 };
 use massa_pos_exports::{PoSConfig, SelectorConfig};
+use massa_signature::KeyPair;
 use massa_time::MassaTime;
 use massa_versioning::versioning::MipStatsConfig;
 use num::rational::Ratio;
+use serde::{Deserialize, Serialize};
+
+use crate::args::Cli;
+
+const MAINNET_CHAIN_ID: u64 = 77658377;
+/// 2024-01-15T10:00:00Z
+const MAINNET_GENESIS_TIMESTAMP: u64 = 1705312800000;
+const BUILDNET_CHAIN_ID: u64 = 77658366;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Network {
+    Mainnet,
+    Buildnet,
+    /// Constants read from the --network_config file (all required)
+    Custom,
+}
+
+impl Network {
+    /// Fields a --network_config file must set for this network
+    fn required_fields(&self) -> &'static [&'static str] {
+        match self {
+            Network::Mainnet => &[],
+            Network::Buildnet => &["genesis_timestamp", "genesis_key"],
+            Network::Custom => &NETWORK_FILE_FIELDS,
+        }
+    }
+}
+
+const NETWORK_FILE_FIELDS: [&str; 7] = [
+    "chain_id",
+    "genesis_timestamp",
+    "genesis_key",
+    "t0",
+    "initial_draw_seed",
+    "roll_price",
+    "block_reward",
+];
+
+/// Network constants used by the `get_*_config` functions and to compute the ids of the dumped blocks
+///
+/// Note: THREAD_COUNT and PERIODS_PER_CYCLE are not part of it, they stay the compiled ones
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    pub network: Network,
+    /// File the constants were (partly) read from
+    pub file: Option<PathBuf>,
+    pub chain_id: u64,
+    pub genesis_timestamp: MassaTime,
+    pub genesis_key: KeyPair,
+    pub t0: MassaTime,
+    pub initial_draw_seed: String,
+    pub roll_price: Amount,
+    pub block_reward: Amount,
+}
+
+/// Content of a --network_config file, every field is optional (the profile value is used otherwise)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NetworkFile {
+    chain_id: Option<u64>,
+    /// Millis since the unix epoch
+    genesis_timestamp: Option<u64>,
+    /// Secret key (S1...)
+    genesis_key: Option<String>,
+    /// Millis
+    t0: Option<u64>,
+    initial_draw_seed: Option<String>,
+    roll_price: Option<String>,
+    block_reward: Option<String>,
+}
+
+impl NetworkFile {
+    fn is_set(&self, field: &str) -> bool {
+        match field {
+            "chain_id" => self.chain_id.is_some(),
+            "genesis_timestamp" => self.genesis_timestamp.is_some(),
+            "genesis_key" => self.genesis_key.is_some(),
+            "t0" => self.t0.is_some(),
+            "initial_draw_seed" => self.initial_draw_seed.is_some(),
+            "roll_price" => self.roll_price.is_some(),
+            "block_reward" => self.block_reward.is_some(),
+            _ => unreachable!("unknown network config field: {}", field),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Constants of a network profile, the other values are the compiled constants
+    fn profile(network: Network) -> Self {
+        let (chain_id, genesis_timestamp) = match network {
+            Network::Mainnet => (
+                MAINNET_CHAIN_ID,
+                MassaTime::from_millis(MAINNET_GENESIS_TIMESTAMP),
+            ),
+            // Note: the buildnet genesis changes at each reset, it must be given with --network_config
+            Network::Buildnet => (BUILDNET_CHAIN_ID, *GENESIS_TIMESTAMP),
+            Network::Custom => (*CHAINID, *GENESIS_TIMESTAMP),
+        };
+        NetworkConfig {
+            network,
+            file: None,
+            chain_id,
+            genesis_timestamp,
+            genesis_key: GENESIS_KEY.clone(),
+            t0: T0,
+            initial_draw_seed: INITIAL_DRAW_SEED.to_string(),
+            roll_price: ROLL_PRICE,
+            block_reward: BLOCK_REWARD,
+        }
+    }
+
+    pub fn load(network: Network, file: Option<&Path>) -> Result<Self, String> {
+        let mut config = NetworkConfig::profile(network);
+        let Some(file) = file else {
+            return match network {
+                Network::Mainnet => Ok(config),
+                Network::Buildnet => Err(
+                    "--network buildnet requires a --network_config file with genesis_timestamp & genesis_key (they change at each reset)"
+                        .to_string(),
+                ),
+                Network::Custom => {
+                    Err("--network custom requires a --network_config file".to_string())
+                }
+            };
+        };
+
+        let content = std::fs::read_to_string(file)
+            .map_err(|e| format!("Cannot read network config {:?}: {}", file, e))?;
+        let values: NetworkFile = toml::from_str(&content)
+            .map_err(|e| format!("Invalid network config {:?}: {}", file, e))?;
+        let invalid = |field: &str, e: String| format!("Invalid {} in {:?}: {}", field, file, e);
+        let missing: Vec<&str> = network
+            .required_fields()
+            .iter()
+            .copied()
+            .filter(|field| !values.is_set(field))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "--network {} requires {} in {:?} ({})",
+                network.to_possible_value().unwrap().get_name(),
+                missing.join(" & "),
+                file,
+                match network {
+                    Network::Buildnet => "they change at each reset",
+                    _ => "no default for a custom network",
+                }
+            ));
+        }
+
+        config.file = Some(file.to_path_buf());
+        if let Some(chain_id) = values.chain_id {
+            config.chain_id = chain_id;
+        }
+        if let Some(genesis_timestamp) = values.genesis_timestamp {
+            config.genesis_timestamp = MassaTime::from_millis(genesis_timestamp);
+        }
+        if let Some(genesis_key) = values.genesis_key {
+            config.genesis_key = KeyPair::from_str(&genesis_key)
+                .map_err(|e| invalid("genesis_key", e.to_string()))?;
+        }
+        if let Some(t0) = values.t0 {
+            config.t0 = MassaTime::from_millis(t0);
+        }
+        if let Some(initial_draw_seed) = values.initial_draw_seed {
+            config.initial_draw_seed = initial_draw_seed;
+        }
+        if let Some(roll_price) = values.roll_price {
+            config.roll_price =
+                Amount::from_str(&roll_price).map_err(|e| invalid("roll_price", e.to_string()))?;
+        }
+        if let Some(block_reward) = values.block_reward {
+            config.block_reward = Amount::from_str(&block_reward)
+                .map_err(|e| invalid("block_reward", e.to_string()))?;
+        }
+        Ok(config)
+    }
+
+    /// Command line arguments selecting this network
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--network".to_string(),
+            self.network
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+        ];
+        if let Some(file) = &self.file {
+            args.push("--network_config".to_string());
+            args.push(file.to_string_lossy().into_owned());
+        }
+        args
+    }
+}
+
+static NETWORK: OnceLock<NetworkConfig> = OnceLock::new();
+
+/// Load the network selected on the command line, exit on error
+fn init_network(cli: &Cli) {
+    let config =
+        NetworkConfig::load(cli.network, cli.network_config.as_deref()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    println!(
+        "Network: {:?}{} (chain id {}, genesis timestamp {}, t0 {} ms)",
+        config.network,
+        config
+            .file
+            .as_ref()
+            .map(|file| format!(" from {:?}", file))
+            .unwrap_or_default(),
+        config.chain_id,
+        config.genesis_timestamp.as_millis(),
+        config.t0.as_millis()
+    );
+    NETWORK
+        .set(config)
+        .expect("Network config already initialized");
+}
+
+/// Mainnet constants, for the tests (no command line)
+#[cfg(test)]
+pub fn init_test_network() {
+    NETWORK.get_or_init(|| NetworkConfig::load(Network::Mainnet, None).unwrap());
+}
+
+/// Network selected on the command line
+pub fn network() -> &'static NetworkConfig {
+    NETWORK.get().expect("Network config not initialized")
+}
+
+/// Settings read from a massa-node config.toml (same layout), the values not found are the defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeSettings {
+    pub execution: ExecutionSettings,
+    pub ledger: LedgerSettings,
+    pub selector: SelectorSettings,
+}
+
+/// Note: execution.hd_cache_path is not read, the module cache is selected with --module_cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutionSettings {
+    pub max_final_events: usize,
+    pub readonly_queue_length: usize,
+    pub cursor_delay: MassaTime,
+    pub stats_time_window_duration: MassaTime,
+    pub max_read_only_gas: u64,
+    pub lru_cache_size: u32,
+    pub hd_cache_size: usize,
+    pub snip_amount: usize,
+    pub broadcast_slot_execution_output_channel_capacity: usize,
+    pub broadcast_slot_execution_traces_channel_capacity: usize,
+    pub execution_traces_limit: usize,
+}
+
+impl Default for ExecutionSettings {
+    fn default() -> Self {
+        ExecutionSettings {
+            max_final_events: 10000,
+            readonly_queue_length: 10,
+            cursor_delay: MassaTime::from_millis(2000),
+            stats_time_window_duration: MassaTime::from_millis(60000),
+            max_read_only_gas: 4_294_967_295,
+            lru_cache_size: 200,
+            hd_cache_size: 2000,
+            snip_amount: 10,
+            broadcast_slot_execution_output_channel_capacity: 5000,
+            broadcast_slot_execution_traces_channel_capacity: 5000,
+            execution_traces_limit: 320,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedgerSettings {
+    /// History of the final state (and of the db) kept
+    pub final_history_length: usize,
+}
+
+impl Default for LedgerSettings {
+    fn default() -> Self {
+        LedgerSettings {
+            final_history_length: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectorSettings {
+    pub max_draw_cache: usize,
+}
+
+impl Default for SelectorSettings {
+    fn default() -> Self {
+        SelectorSettings {
+            max_draw_cache: SELECTOR_DRAW_CACHE_SIZE,
+        }
+    }
+}
+
+impl NodeSettings {
+    pub fn load(file: Option<&Path>) -> Result<Self, String> {
+        let Some(file) = file else {
+            return Ok(NodeSettings::default());
+        };
+        let content = std::fs::read_to_string(file)
+            .map_err(|e| format!("Cannot read node config {:?}: {}", file, e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid node config {:?}: {}", file, e))
+    }
+
+    /// Print the settings used (in the massa-node config.toml layout)
+    pub fn print(&self) {
+        println!(
+            "Node settings{}:",
+            NODE_CONFIG_FILE
+                .get()
+                .and_then(|file| file.as_ref())
+                .map(|file| format!(" (from {:?})", file))
+                .unwrap_or_else(|| " (defaults)".to_string())
+        );
+        println!(
+            "{}",
+            toml::to_string_pretty(self).expect("Failed to serialize node settings")
+        );
+    }
+}
+
+static NODE_SETTINGS: OnceLock<NodeSettings> = OnceLock::new();
+static NODE_CONFIG_FILE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Load the network & node settings selected on the command line, exit on error
+/// (to be called once, before any command)
+pub fn init_config(cli: &Cli) {
+    init_network(cli);
+    let settings = NodeSettings::load(cli.node_config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    NODE_CONFIG_FILE
+        .set(cli.node_config.clone())
+        .expect("Node settings already initialized");
+    NODE_SETTINGS
+        .set(settings)
+        .expect("Node settings already initialized");
+}
+
+/// Node settings selected on the command line
+pub fn node_settings() -> &'static NodeSettings {
+    NODE_SETTINGS.get().expect("Node settings not initialized")
+}
+
+/// Command line arguments selecting the network & node settings (for the child processes)
+pub fn config_args() -> Vec<String> {
+    let mut args = network().args();
+    if let Some(file) = NODE_CONFIG_FILE.get().and_then(|file| file.as_ref()) {
+        args.push("--node_config".to_string());
+        args.push(file.to_string_lossy().into_owned());
+    }
+    args
+}
 
 pub fn get_db_config(path: PathBuf) -> MassaDBConfig {
     MassaDBConfig {
         path,
-        max_history_length: 100,
+        max_history_length: node_settings().ledger.final_history_length,
         max_versioning_elements_size: MAX_BOOTSTRAP_VERSIONING_ELEMENTS_SIZE as usize,
         max_final_state_elements_size: MAX_BOOTSTRAP_FINAL_STATE_PARTS_SIZE as usize,
         thread_count: THREAD_COUNT,
@@ -108,31 +479,51 @@ pub fn get_final_state_config(
         pos_config,
         executed_ops_config,
         executed_denunciations_config,
-        final_history_length: 100,
+        final_history_length: node_settings().ledger.final_history_length,
         thread_count: THREAD_COUNT,
         periods_per_cycle: PERIODS_PER_CYCLE,
-        initial_seed_string: INITIAL_DRAW_SEED.into(),
+        initial_seed_string: network().initial_draw_seed.clone(),
         initial_rolls_path,
         endorsement_count: ENDORSEMENT_COUNT,
         max_executed_denunciations_length: MAX_DENUNCIATION_CHANGES_LENGTH,
         max_denunciations_per_block_header: MAX_DENUNCIATIONS_PER_BLOCK_HEADER,
-        t0: T0,
-        genesis_timestamp: *GENESIS_TIMESTAMP,
+        t0: network().t0,
+        genesis_timestamp: network().genesis_timestamp,
     }
 }
 
 pub fn get_selector_config() -> SelectorConfig {
     SelectorConfig {
-        max_draw_cache: SELECTOR_DRAW_CACHE_SIZE,
+        max_draw_cache: node_settings().selector.max_draw_cache,
         channel_size: CHANNEL_SIZE,
         thread_count: THREAD_COUNT,
         endorsement_count: ENDORSEMENT_COUNT,
         periods_per_cycle: PERIODS_PER_CYCLE,
-        genesis_address: Address::from_public_key(&GENESIS_KEY.get_public_key()),
+        genesis_address: Address::from_public_key(&network().genesis_key.get_public_key()),
     }
 }
 
-pub fn get_execution_config(last_start_period: u64, gas_costs_folder: &PathBuf) -> ExecutionConfig {
+const ABI_GAS_COSTS_FILE: &str = "abi_gas_costs.json";
+const WASM_GAS_COSTS_FILE: &str = "wasm_gas_costs.json";
+
+/// Check that the gas cost tables are in the folder (before copying it)
+pub fn check_gas_costs_folder(gas_costs_folder: &Path) -> Result<(), String> {
+    for file_name in [ABI_GAS_COSTS_FILE, WASM_GAS_COSTS_FILE] {
+        if !gas_costs_folder.join(file_name).is_file() {
+            return Err(format!(
+                "Gas costs folder {:?} has no {}",
+                gas_costs_folder, file_name
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn get_execution_config(
+    last_start_period: u64,
+    gas_costs_folder: &PathBuf,
+    hd_cache_path: PathBuf,
+) -> ExecutionConfig {
     // Storage costs constants
     let storage_costs_constants = StorageCostsConstants {
         ledger_cost_per_byte: LEDGER_COST_PER_BYTE,
@@ -144,58 +535,254 @@ pub fn get_execution_config(last_start_period: u64, gas_costs_folder: &PathBuf)
 
     // gas costs
     let gas_costs = GasCosts::new(
-        gas_costs_folder.join("abi_gas_costs.json"), // SETTINGS.execution.abi_gas_costs_file.clone(),
-        gas_costs_folder.join("wasm_gas_costs.json"), // SETTINGS.execution.wasm_gas_costs_file.clone(),
+        gas_costs_folder.join(ABI_GAS_COSTS_FILE), // SETTINGS.execution.abi_gas_costs_file.clone(),
+        gas_costs_folder.join(WASM_GAS_COSTS_FILE), // SETTINGS.execution.wasm_gas_costs_file.clone(),
     )
     .expect("Failed to load gas costs");
 
+    let settings = &node_settings().execution;
     ExecutionConfig {
-        max_final_events: 10000,   // SETTINGS.execution.max_final_events,
-        readonly_queue_length: 10, // SETTINGS.execution.readonly_queue_length,
-        cursor_delay: MassaTime::from_millis(2000), // SETTINGS.execution.cursor_delay,
+        max_final_events: settings.max_final_events,
+        readonly_queue_length: settings.readonly_queue_length,
+        cursor_delay: settings.cursor_delay,
         max_async_gas: MAX_ASYNC_GAS,
         async_msg_cst_gas_cost: ASYNC_MSG_CST_GAS_COST,
         max_gas_per_block: MAX_GAS_PER_BLOCK,
-        roll_price: ROLL_PRICE,
+        roll_price: network().roll_price,
         thread_count: THREAD_COUNT,
-        t0: T0,
-        genesis_timestamp: *GENESIS_TIMESTAMP,
-        block_reward: BLOCK_REWARD,
+        t0: network().t0,
+        genesis_timestamp: network().genesis_timestamp,
+        block_reward: network().block_reward,
         endorsement_count: ENDORSEMENT_COUNT as u64,
         operation_validity_period: OPERATION_VALIDITY_PERIODS,
         periods_per_cycle: PERIODS_PER_CYCLE,
-        stats_time_window_duration: MassaTime::from_millis(60000), // SETTINGS.execution.stats_time_window_duration,
+        stats_time_window_duration: settings.stats_time_window_duration,
         max_miss_ratio: *POS_MISS_RATE_DEACTIVATION_THRESHOLD,
         max_datastore_key_length: MAX_DATASTORE_KEY_LENGTH,
         max_bytecode_size: MAX_BYTECODE_LENGTH,
         max_datastore_value_size: MAX_DATASTORE_VALUE_LENGTH,
         storage_costs_constants,
-        max_read_only_gas: 4_294_967_295, // SETTINGS.execution.max_read_only_gas,
+        max_read_only_gas: settings.max_read_only_gas,
         gas_costs: gas_costs.clone(),
         base_operation_gas_cost: BASE_OPERATION_GAS_COST,
         last_start_period, // final_state.read().get_last_start_period(),
-        hd_cache_path: PathBuf::from("storage/cache/rocks_db"), // SETTINGS.execution.hd_cache_path.clone(),
-        lru_cache_size: 200, // SETTINGS.execution.lru_cache_size,
-        hd_cache_size: 2000, // SETTINGS.execution.hd_cache_size,
-        snip_amount: 10,     // SETTINGS.execution.snip_amount,
+        hd_cache_path,
+        lru_cache_size: settings.lru_cache_size,
+        hd_cache_size: settings.hd_cache_size,
+        snip_amount: settings.snip_amount,
         roll_count_to_slash_on_denunciation: ROLL_COUNT_TO_SLASH_ON_DENUNCIATION,
         denunciation_expire_periods: DENUNCIATION_EXPIRE_PERIODS,
-        broadcast_enabled: false, // SETTINGS.api.enable_broadcast,
-        broadcast_slot_execution_output_channel_capacity: 5000, // SETTINGS
-        // .execution
-        // .broadcast_slot_execution_output_channel_capacity,
+        broadcast_enabled: true, // SETTINGS.api.enable_broadcast (required to receive slot execution outputs),
+        broadcast_slot_execution_output_channel_capacity: settings
+            .broadcast_slot_execution_output_channel_capacity,
         max_event_size: MAX_EVENT_DATA_SIZE,
         max_function_length: MAX_FUNCTION_NAME_LENGTH,
         max_parameter_length: MAX_PARAMETERS_SIZE,
-        chain_id: *CHAINID,
+        chain_id: network().chain_id,
         #[cfg(feature = "execution-trace")]
         broadcast_traces_enabled: true,
         #[cfg(not(feature = "execution-trace"))]
         broadcast_traces_enabled: false,
-        broadcast_slot_execution_traces_channel_capacity: 5000, // SETTINGS
-        // .execution
-        // .broadcast_slot_execution_traces_channel_capacity,
-        max_execution_traces_slot_limit: 320, // SETTINGS.execution.execution_traces_limit,
+        broadcast_slot_execution_traces_channel_capacity: settings
+            .broadcast_slot_execution_traces_channel_capacity,
+        max_execution_traces_slot_limit: settings.execution_traces_limit,
         block_dump_folder_path: PathBuf::from(""),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(dir: &Path, content: &str) -> PathBuf {
+        let path = dir.join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn network_file(genesis_key: &KeyPair) -> String {
+        format!(
+            r#"chain_id = 12345
+genesis_timestamp = 1704289800000
+genesis_key = "{}"
+t0 = 8000
+initial_draw_seed = "custom seed"
+roll_price = "10"
+block_reward = "0.5"
+"#,
+            genesis_key
+        )
+    }
+
+    #[test]
+    fn mainnet_without_file() {
+        let config = NetworkConfig::load(Network::Mainnet, None).unwrap();
+        assert_eq!(config.chain_id, MAINNET_CHAIN_ID);
+        assert_eq!(
+            config.genesis_timestamp.as_millis(),
+            MAINNET_GENESIS_TIMESTAMP
+        );
+        assert_eq!(config.file, None);
+    }
+
+    #[test]
+    fn mainnet_overridden_by_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_config(dir.path(), "t0 = 8000\n");
+        let config = NetworkConfig::load(Network::Mainnet, Some(&file)).unwrap();
+        assert_eq!(config.t0.as_millis(), 8000);
+        assert_eq!(config.chain_id, MAINNET_CHAIN_ID);
+        assert_eq!(config.file, Some(file));
+    }
+
+    #[test]
+    fn buildnet_requires_genesis() {
+        assert!(NetworkConfig::load(Network::Buildnet, None).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_config(dir.path(), "genesis_timestamp = 1704289800000\n");
+        let e = NetworkConfig::load(Network::Buildnet, Some(&file)).unwrap_err();
+        assert!(e.contains("requires genesis_key in"), "{}", e);
+
+        let file = write_config(dir.path(), "t0 = 16000\n");
+        let e = NetworkConfig::load(Network::Buildnet, Some(&file)).unwrap_err();
+        assert!(
+            e.contains("requires genesis_timestamp & genesis_key in"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn buildnet_with_genesis() {
+        let dir = tempfile::tempdir().unwrap();
+        let genesis_key = KeyPair::generate(0).unwrap();
+        let file = write_config(
+            dir.path(),
+            &format!(
+                "genesis_timestamp = 1704289800000\ngenesis_key = \"{}\"\n",
+                genesis_key
+            ),
+        );
+        let config = NetworkConfig::load(Network::Buildnet, Some(&file)).unwrap();
+        assert_eq!(config.chain_id, BUILDNET_CHAIN_ID);
+        assert_eq!(config.genesis_timestamp.as_millis(), 1704289800000);
+        assert_eq!(
+            config.genesis_key.get_public_key(),
+            genesis_key.get_public_key()
+        );
+        assert_eq!(config.t0, T0);
+    }
+
+    #[test]
+    fn custom_requires_every_constant() {
+        assert!(NetworkConfig::load(Network::Custom, None).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let genesis_key = KeyPair::generate(0).unwrap();
+        let file = write_config(
+            dir.path(),
+            &format!(
+                "genesis_timestamp = 1704289800000\ngenesis_key = \"{}\"\n",
+                genesis_key
+            ),
+        );
+        let e = NetworkConfig::load(Network::Custom, Some(&file)).unwrap_err();
+        assert!(
+            e.contains("chain_id & t0 & initial_draw_seed & roll_price & block_reward"),
+            "{}",
+            e
+        );
+
+        let file = write_config(dir.path(), &network_file(&genesis_key));
+        let config = NetworkConfig::load(Network::Custom, Some(&file)).unwrap();
+        assert_eq!(config.chain_id, 12345);
+        assert_eq!(config.t0.as_millis(), 8000);
+        assert_eq!(config.initial_draw_seed, "custom seed");
+        assert_eq!(config.roll_price, Amount::from_str("10").unwrap());
+        assert_eq!(config.block_reward, Amount::from_str("0.5").unwrap());
+    }
+
+    #[test]
+    fn node_config_of_massa_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_config(
+            dir.path(),
+            r#"
+[logging]
+level = 3
+
+[metrics]
+enabled = true
+bind = "[::]:31248"
+
+[execution]
+max_final_events = 5000
+cursor_delay = 3000
+hd_cache_path = "storage/cache/rocks_db"
+
+[ledger]
+initial_ledger_path = "base_config/initial_ledger.json"
+final_history_length = 50
+
+[selector]
+max_draw_cache = 12
+
+[factory]
+initial_delay = 100
+staking_wallet_path = "config/staking_wallets"
+"#,
+        );
+        let settings = NodeSettings::load(Some(&file)).unwrap();
+        assert_eq!(settings.execution.max_final_events, 5000);
+        assert_eq!(settings.execution.cursor_delay.as_millis(), 3000);
+        assert_eq!(settings.ledger.final_history_length, 50);
+        assert_eq!(settings.selector.max_draw_cache, 12);
+    }
+
+    #[test]
+    fn node_config_defaults() {
+        let defaults = NodeSettings::default();
+        let settings = NodeSettings::load(None).unwrap();
+        assert_eq!(
+            settings.execution.max_final_events,
+            defaults.execution.max_final_events
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_config(dir.path(), "[execution]\nlru_cache_size = 10\n");
+        let settings = NodeSettings::load(Some(&file)).unwrap();
+        assert_eq!(settings.execution.lru_cache_size, 10);
+        assert_eq!(
+            settings.execution.hd_cache_size,
+            defaults.execution.hd_cache_size
+        );
+        assert_eq!(
+            settings.execution.max_read_only_gas,
+            defaults.execution.max_read_only_gas
+        );
+        assert_eq!(
+            settings.ledger.final_history_length,
+            defaults.ledger.final_history_length
+        );
+        assert_eq!(
+            settings.selector.max_draw_cache,
+            defaults.selector.max_draw_cache
+        );
+    }
+
+    #[test]
+    fn invalid_node_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_config(dir.path(), "[execution]\nmax_final_events = \"many\"\n");
+        assert!(NodeSettings::load(Some(&file)).is_err());
+        assert!(NodeSettings::load(Some(&dir.path().join("missing.toml"))).is_err());
+    }
+
+    #[test]
+    fn unknown_network_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_config(dir.path(), "genesis_time = 1704289800000\n");
+        assert!(NetworkConfig::load(Network::Mainnet, Some(&file)).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use massa_models::slot::Slot;
+use massa_proto_rs::massa::model::v1::{self as grpc_model};
+use prost::Message;
+
+use crate::args::ConvertBlocksArgs;
+use crate::block_storage::{find_gaps, BlockStorage, BlocksFormat};
+
+/// Copy the dumped blocks of a slot range from one layout (file / RocksDB) to the other
+pub fn convert_blocks(args: &ConvertBlocksArgs) {
+    let input_format = args.input_format.resolve(&args.input).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let output_format = match (args.output_format, input_format) {
+        (BlocksFormat::Auto, BlocksFormat::File) => BlocksFormat::Rocksdb,
+        (BlocksFormat::Auto, _) => BlocksFormat::File,
+        (output_format, _) => output_format,
+    };
+    println!(
+        "Converting blocks from {:?} ({:?}) to {:?} ({:?})",
+        args.input, input_format, args.output, output_format
+    );
+
+    let from_slot = args
+        .from_slot
+        .map(|(period, thread)| Slot::new(period, thread));
+    let until_slot = args
+        .until_slot
+        .map(|(period, thread)| Slot::new(period, thread));
+    let input = BlockStorage::open(&args.input, input_format).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let slots = input.slots(from_slot, until_slot);
+    if slots.is_empty() {
+        eprintln!(
+            "No dumped block found in {:?} for the given range",
+            args.input
+        );
+        std::process::exit(1);
+    }
+
+    let mut output = BlockStorage::create(&args.output, output_format).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut converted = 0;
+    let mut invalid_slots = Vec::new();
+    for slot in slots.iter() {
+        let content = match input.read(slot) {
+            Some(Ok(content)) => content,
+            Some(Err(e)) => {
+                invalid_slots.push((*slot, e));
+                continue;
+            }
+            None => {
+                invalid_slots.push((*slot, "cannot be read".to_string()));
+                continue;
+            }
+        };
+        if let Err(e) = grpc_model::FilledBlock::decode(&content[..]) {
+            invalid_slots.push((*slot, format!("cannot be decoded: {}", e)));
+            continue;
+        }
+        output.write(slot, &content);
+        converted += 1;
+    }
+
+    println!(
+        "{} blocks converted (slot {} to slot {})",
+        converted,
+        slots.first().unwrap(),
+        slots.last().unwrap()
+    );
+
+    let gaps = find_gaps(&slots);
+    if !gaps.is_empty() {
+        println!(
+            "{} gaps ({} slots without block, missed slots or missing dumps):",
+            gaps.len(),
+            gaps.iter().map(|gap| gap.count).sum::<u64>()
+        );
+        for gap in gaps.iter() {
+            println!(
+                "  slot {} to slot {} ({} slots)",
+                gap.first, gap.last, gap.count
+            );
+        }
+    }
+
+    if !invalid_slots.is_empty() {
+        println!("{} blocks not converted:", invalid_slots.len());
+        for (slot, reason) in invalid_slots.iter() {
+            println!("  slot {}: {}", slot, reason);
+        }
+        std::process::exit(1);
+    }
+}
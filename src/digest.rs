@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use massa_db_exports::{
+    MassaDBController, ASYNC_POOL_PREFIX, CYCLE_HISTORY_PREFIX, DEFERRED_CREDITS_PREFIX,
+    LEDGER_PREFIX, STATE_CF,
+};
+use massa_hash::{Hash, HASH_SIZE_BYTES};
+use massa_models::address::{Address, AddressDeserializer, AddressSerializer};
+use massa_models::prehash::PreHashMap;
+use massa_models::slot::Slot;
+use massa_serialization::{DeserializeError, Deserializer, Serializer};
+
+const DIGEST_HEADER: &str = "# period\tthread\tfinal_state\tledger\tasync_pool\tpos";
+const NOT_OBSERVED: &str = "-";
+
+/// Hashes of the final state at the end of a slot
+///
+/// Sub-hashes are the xor of the hashes of every (key, value) of the corresponding db prefix.
+/// A hash is None if the final state could not be observed at this exact slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDigest {
+    pub slot: Slot,
+    pub final_state: Option<String>,
+    pub ledger: Option<String>,
+    pub async_pool: Option<String>,
+    pub pos: Option<String>,
+}
+
+impl StateDigest {
+    pub fn not_observed(slot: Slot) -> Self {
+        Self {
+            slot,
+            final_state: None,
+            ledger: None,
+            async_pool: None,
+            pos: None,
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, &Option<String>); 4] {
+        [
+            ("final_state", &self.final_state),
+            ("ledger", &self.ledger),
+            ("async_pool", &self.async_pool),
+            ("pos", &self.pos),
+        ]
+    }
+
+    /// Names of the hashes that differ (hashes not observed on either side are ignored)
+    pub fn diff(&self, other: &StateDigest) -> Vec<&'static str> {
+        self.fields()
+            .into_iter()
+            .zip(other.fields())
+            .filter_map(|((name, a), (_, b))| match (a, b) {
+                (Some(a), Some(b)) if a != b => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn to_line(&self) -> String {
+        let [final_state, ledger, async_pool, pos] = self
+            .fields()
+            .map(|(_, h)| h.as_deref().unwrap_or(NOT_OBSERVED).to_string());
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.slot.period, self.slot.thread, final_state, ledger, async_pool, pos
+        )
+    }
+
+    fn from_line(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return Err(format!("expected 6 fields, got {}", fields.len()));
+        }
+        let period = fields[0]
+            .parse::<u64>()
+            .map_err(|e| format!("invalid period: {}", e))?;
+        let thread = fields[1]
+            .parse::<u8>()
+            .map_err(|e| format!("invalid thread: {}", e))?;
+        let hash = |s: &str| (s != NOT_OBSERVED).then(|| s.to_string());
+
+        Ok(Self {
+            slot: Slot::new(period, thread),
+            final_state: hash(fields[2]),
+            ledger: hash(fields[3]),
+            async_pool: hash(fields[4]),
+            pos: hash(fields[5]),
+        })
+    }
+}
+
+impl fmt::Display for StateDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "slot {}", self.slot)?;
+        for (name, hash) in self.fields() {
+            write!(f, ", {}: {}", name, hash.as_deref().unwrap_or(NOT_OBSERVED))?;
+        }
+        Ok(())
+    }
+}
+
+fn xor_into(acc: &mut [u8; HASH_SIZE_BYTES], key: &[u8], value: &[u8]) {
+    let mut data = Vec::with_capacity(8 + key.len() + value.len());
+    data.extend((key.len() as u64).to_le_bytes());
+    data.extend(key);
+    data.extend(value);
+    let hash = Hash::compute_from(&data).into_bytes();
+    acc.iter_mut().zip(hash).for_each(|(a, h)| *a ^= h);
+}
+
+fn prefix_hash(db: &dyn MassaDBController, prefixes: &[&str]) -> [u8; HASH_SIZE_BYTES] {
+    let mut acc = [0u8; HASH_SIZE_BYTES];
+    for prefix in prefixes {
+        for (key, value) in db.prefix_iterator_cf(STATE_CF, prefix.as_bytes()) {
+            xor_into(&mut acc, &key, &value);
+        }
+    }
+    acc
+}
+
+fn hash_to_string(acc: &[u8; HASH_SIZE_BYTES]) -> String {
+    Hash::from_bytes(acc).to_string()
+}
+
+/// Compute state digests of the final state
+///
+/// The ledger hash is maintained per address (only the addresses modified by a slot are
+/// rehashed), async pool & PoS hashes are recomputed at every slot
+pub struct StateDigester {
+    ledger_per_address: PreHashMap<Address, [u8; HASH_SIZE_BYTES]>,
+    ledger: [u8; HASH_SIZE_BYTES],
+}
+
+impl StateDigester {
+    pub fn new(db: &dyn MassaDBController) -> Self {
+        let address_deserializer = AddressDeserializer::new();
+        let mut ledger_per_address: PreHashMap<Address, [u8; HASH_SIZE_BYTES]> =
+            PreHashMap::default();
+        let mut ledger = [0u8; HASH_SIZE_BYTES];
+
+        for (key, value) in db.prefix_iterator_cf(STATE_CF, LEDGER_PREFIX.as_bytes()) {
+            let (_, address) = address_deserializer
+                .deserialize::<DeserializeError>(&key[LEDGER_PREFIX.len()..])
+                .expect("Failed to deserialize address from ledger key");
+            xor_into(
+                ledger_per_address
+                    .entry(address)
+                    .or_insert([0u8; HASH_SIZE_BYTES]),
+                &key,
+                &value,
+            );
+            xor_into(&mut ledger, &key, &value);
+        }
+
+        Self {
+            ledger_per_address,
+            ledger,
+        }
+    }
+
+    /// Update the ledger hash for the given addresses
+    pub fn update_addresses<'a>(
+        &mut self,
+        db: &dyn MassaDBController,
+        addresses: impl Iterator<Item = &'a Address>,
+    ) {
+        let address_serializer = AddressSerializer::new();
+        for address in addresses {
+            let mut prefix = LEDGER_PREFIX.as_bytes().to_vec();
+            address_serializer
+                .serialize(address, &mut prefix)
+                .expect("Failed to serialize address");
+
+            let mut address_hash = [0u8; HASH_SIZE_BYTES];
+            for (key, value) in db.prefix_iterator_cf(STATE_CF, &prefix) {
+                xor_into(&mut address_hash, &key, &value);
+            }
+
+            let previous = self
+                .ledger_per_address
+                .insert(*address, address_hash)
+                .unwrap_or([0u8; HASH_SIZE_BYTES]);
+            self.ledger
+                .iter_mut()
+                .zip(previous.iter().zip(address_hash))
+                .for_each(|(acc, (p, n))| *acc ^= p ^ n);
+        }
+    }
+
+    /// Digest of the current final state (db must be at `slot`)
+    pub fn digest(&self, db: &dyn MassaDBController, slot: Slot) -> StateDigest {
+        StateDigest {
+            slot,
+            final_state: Some(db.get_xof_db_hash().to_string()),
+            ledger: Some(hash_to_string(&self.ledger)),
+            async_pool: Some(hash_to_string(&prefix_hash(db, &[ASYNC_POOL_PREFIX]))),
+            pos: Some(hash_to_string(&prefix_hash(
+                db,
+                &[CYCLE_HISTORY_PREFIX, DEFERRED_CREDITS_PREFIX],
+            ))),
+        }
+    }
+}
+
+/// Append state digests to a digest file
+pub struct DigestWriter {
+    writer: BufWriter<File>,
+}
+
+impl DigestWriter {
+    pub fn new(path: &Path) -> Self {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Failed to open digest file {:?}: {}", path, e));
+        let mut writer = BufWriter::new(file);
+        if writer.get_ref().metadata().map(|m| m.len()).unwrap_or(0) == 0 {
+            writeln!(writer, "{}", DIGEST_HEADER).expect("Failed to write digest file");
+        }
+        Self { writer }
+    }
+
+    pub fn write(&mut self, digest: &StateDigest) {
+        writeln!(self.writer, "{}", digest.to_line()).expect("Failed to write digest file");
+        self.writer.flush().expect("Failed to flush digest file");
+    }
+}
+
+pub fn read_digest_file(path: &Path) -> Result<BTreeMap<Slot, StateDigest>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {:?}: {}", path, e))?;
+    let mut digests = BTreeMap::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let digest = StateDigest::from_line(&line)
+            .map_err(|e| format!("{:?}, line {}: {}", path, index + 1, e))?;
+        // same slot written twice: keep the latest one
+        digests.insert(digest.slot, digest);
+    }
+    Ok(digests)
+}
+
+/// First slot (present in both) where the digests differ
+pub fn first_divergence<'a>(
+    digests_a: &'a BTreeMap<Slot, StateDigest>,
+    digests_b: &'a BTreeMap<Slot, StateDigest>,
+) -> Option<(&'a StateDigest, &'a StateDigest)> {
+    digests_a.iter().find_map(|(slot, digest_a)| {
+        let digest_b = digests_b.get(slot)?;
+        (!digest_a.diff(digest_b).is_empty()).then_some((digest_a, digest_b))
+    })
+}
+
+/// Line up two digest files, print the first slot where they differ
+///
+/// Return true if no difference was found
+pub fn compare_digests(path_a: &Path, path_b: &Path) -> bool {
+    let (digests_a, digests_b) = match (read_digest_file(path_a), read_digest_file(path_b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to read digest file: {}", e);
+            return false;
+        }
+    };
+
+    if let Some((digest_a, digest_b)) = first_divergence(&digests_a, &digests_b) {
+        println!(
+            "First divergent slot: {} ({} differ)",
+            digest_a.slot,
+            digest_a.diff(digest_b).join(", ")
+        );
+        println!("  {:?}: {}", path_a, digest_a);
+        println!("  {:?}: {}", path_b, digest_b);
+        return false;
+    }
+
+    let only_a = digests_a
+        .keys()
+        .filter(|s| !digests_b.contains_key(s))
+        .count();
+    let only_b = digests_b
+        .keys()
+        .filter(|s| !digests_a.contains_key(s))
+        .count();
+    let common = digests_a
+        .keys()
+        .filter(|s| digests_b.contains_key(s))
+        .count();
+
+    println!(
+        "No divergence found on {} common slot(s) ({} slot(s) only in {:?}, {} slot(s) only in {:?})",
+        common, only_a, path_a, only_b, path_b
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(period: u64, thread: u8, final_state: &str) -> StateDigest {
+        StateDigest {
+            slot: Slot::new(period, thread),
+            final_state: Some(final_state.to_string()),
+            ledger: Some("ledger".to_string()),
+            async_pool: Some("async_pool".to_string()),
+            pos: Some("pos".to_string()),
+        }
+    }
+
+    fn write_digest_file(dir: &Path, name: &str, digests: &[StateDigest]) -> std::path::PathBuf {
+        let path = dir.join(name);
+        let mut writer = DigestWriter::new(&path);
+        for digest in digests {
+            writer.write(digest);
+        }
+        path
+    }
+
+    #[test]
+    fn line_round_trip() {
+        let observed = digest(40, 2, "final");
+        assert_eq!(
+            StateDigest::from_line(&observed.to_line()).unwrap(),
+            observed
+        );
+
+        let not_observed = StateDigest::not_observed(Slot::new(41, 0));
+        assert_eq!(not_observed.to_line(), "41\t0\t-\t-\t-\t-");
+        assert_eq!(
+            StateDigest::from_line(&not_observed.to_line()).unwrap(),
+            not_observed
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(StateDigest::from_line("40\t2\ta\tb\tc").is_err());
+        assert!(StateDigest::from_line("x\t2\ta\tb\tc\td").is_err());
+        assert!(StateDigest::from_line("40\t256\ta\tb\tc\td").is_err());
+    }
+
+    #[test]
+    fn read_skips_header_and_keeps_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_digest_file(
+            dir.path(),
+            "digest.tsv",
+            &[digest(40, 0, "a"), digest(40, 1, "b"), digest(40, 0, "c")],
+        );
+        let digests = read_digest_file(&path).unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[&Slot::new(40, 0)].final_state.as_deref(), Some("c"));
+
+        std::fs::write(dir.path().join("bad.tsv"), "# header\n40\t0\ta\n").unwrap();
+        let e = read_digest_file(&dir.path().join("bad.tsv")).unwrap_err();
+        assert!(e.contains("line 2"), "{}", e);
+    }
+
+    #[test]
+    fn diff_ignores_not_observed() {
+        let a = digest(40, 0, "a");
+        let mut b = digest(40, 0, "b");
+        b.ledger = None;
+        assert_eq!(a.diff(&b), vec!["final_state"]);
+        assert!(a.diff(&StateDigest::not_observed(a.slot)).is_empty());
+    }
+
+    #[test]
+    fn compare_digest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = write_digest_file(
+            dir.path(),
+            "a.tsv",
+            &[digest(40, 0, "x"), digest(40, 1, "y"), digest(40, 2, "z")],
+        );
+        let same = write_digest_file(
+            dir.path(),
+            "same.tsv",
+            &[digest(40, 1, "y"), digest(40, 2, "z"), digest(40, 3, "w")],
+        );
+        let diverged = write_digest_file(
+            dir.path(),
+            "diverged.tsv",
+            &[digest(40, 0, "x"), digest(40, 1, "other")],
+        );
+
+        assert!(compare_digests(&a, &same));
+        assert!(!compare_digests(&a, &diverged));
+        assert!(!compare_digests(&a, &dir.path().join("missing.tsv")));
+    }
+}
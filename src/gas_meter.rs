@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use massa_execution_exports::{
+    ExecutionController, ExecutionStackElement, ReadOnlyExecutionRequest, ReadOnlyExecutionTarget,
+};
+use massa_models::amount::Amount;
+use massa_models::operation::{OperationType, SecureShareOperation};
+use tracing::warn;
+
+use crate::grpc_conv::DumpedBlock;
+
+/// Gas used by the smart contract operations (ExecuteSC & CallSC) of the fed blocks
+///
+/// The execution does not report the gas used by an operation: each operation is executed again,
+/// read-only, on the final state just before its block is fed (the blocks are then fed one at a
+/// time). An operation reading the changes of a previous operation of the same block may use a
+/// different amount of gas than in the block.
+pub struct GasMeter {
+    execution_controller: Box<dyn ExecutionController>,
+}
+
+impl GasMeter {
+    pub fn new(execution_controller: Box<dyn ExecutionController>) -> Self {
+        Self {
+            execution_controller,
+        }
+    }
+
+    /// Gas used by the smart contract operations of the block (None: the read-only execution failed)
+    pub fn measure(&self, dumped_block: &DumpedBlock) -> BTreeMap<String, Option<u64>> {
+        dumped_block
+            .operations
+            .iter()
+            .filter_map(|op| {
+                let request = read_only_request(op)?;
+                let gas_used = match self.execution_controller.execute_readonly_request(request) {
+                    Ok(output) => Some(output.gas_cost),
+                    Err(e) => {
+                        warn!("Cannot measure the gas used by operation {}: {}", op.id, e);
+                        None
+                    }
+                };
+                Some((op.id.to_string(), gas_used))
+            })
+            .collect()
+    }
+}
+
+/// Same execution as the operation, read-only (None if the operation executes no bytecode)
+fn read_only_request(op: &SecureShareOperation) -> Option<ReadOnlyExecutionRequest> {
+    let sender = op.content_creator_address;
+    let (max_gas, coins, datastore, target) = match &op.content.op {
+        OperationType::ExecuteSC {
+            data,
+            max_gas,
+            datastore,
+            ..
+        } => (
+            *max_gas,
+            Amount::zero(),
+            Some(datastore.clone()),
+            ReadOnlyExecutionTarget::BytecodeExecution(data.clone()),
+        ),
+        OperationType::CallSC {
+            target_addr,
+            target_func,
+            param,
+            max_gas,
+            coins,
+        } => (
+            *max_gas,
+            *coins,
+            None,
+            ReadOnlyExecutionTarget::FunctionCall {
+                target_addr: *target_addr,
+                target_func: target_func.clone(),
+                parameter: param.clone(),
+            },
+        ),
+        _ => return None,
+    };
+    Some(ReadOnlyExecutionRequest {
+        max_gas,
+        call_stack: vec![ExecutionStackElement {
+            address: sender,
+            coins: Amount::zero(),
+            owned_addresses: vec![sender],
+            operation_datastore: datastore,
+        }],
+        target,
+        coins: Some(coins),
+        fee: Some(op.content.fee),
+    })
+}
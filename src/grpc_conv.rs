@@ -1,190 +1,382 @@
+use std::fmt;
 use std::str::FromStr;
 
-use massa_serialization::Serializer;
 use massa_hash::Hash;
-use massa_models::address::Address;
 use massa_models::amount::{Amount, AMOUNT_DECIMAL_SCALE};
 use massa_models::block::{Block, BlockSerializer, SecureShareBlock};
 use massa_models::block_header::{BlockHeader, BlockHeaderSerializer, SecuredHeader};
 use massa_models::block_id::BlockId;
-use massa_models::config::CHAINID;
-use massa_models::endorsement::{Endorsement, EndorsementId, EndorsementSerializer, SecureShareEndorsement};
-use massa_models::operation::{Operation, OperationId, OperationSerializer, OperationType, SecureShareOperation};
+use massa_models::config::{ENDORSEMENT_COUNT, THREAD_COUNT};
+use massa_models::denunciation::{Denunciation, DenunciationDeserializer};
+use massa_models::endorsement::{
+    Endorsement, EndorsementId, EndorsementSerializer, SecureShareEndorsement,
+};
+use massa_models::operation::{
+    Operation, OperationId, OperationSerializer, OperationType, SecureShareOperation,
+};
 use massa_models::secure_share::{Id, SecureShare, SecureShareContent};
-use massa_signature::{PublicKey, Signature};
+use massa_models::slot::{Slot, SlotSerializer};
 use massa_proto_rs::massa::model::v1::{self as grpc_model};
+use massa_serialization::{DeserializeError, Deserializer, Serializer, U32VarIntSerializer};
+use massa_signature::{PublicKey, Signature};
+use massa_storage::Storage;
+
+use crate::config::network;
+
+/// Type ids of the denunciations in the massa serialization
+const DENUNCIATION_TYPE_ENDORSEMENT: u32 = 0;
+const DENUNCIATION_TYPE_BLOCK_HEADER: u32 = 1;
+
+/// Error while converting a dumped block (gRPC types) to Massa types
+#[derive(Debug, Clone)]
+pub struct ConversionError {
+    /// Slot of the block (if known)
+    pub slot: Option<Slot>,
+    /// Path of the invalid field in the FilledBlock (ex: `header.content.parents[3]`)
+    pub field: String,
+    pub reason: String,
+}
+
+impl ConversionError {
+    pub fn new(field: &str, reason: impl fmt::Display) -> Self {
+        Self {
+            slot: None,
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn missing(field: &str) -> Self {
+        Self::new(field, "missing")
+    }
+
+    /// Prefix the field path with the path of the enclosing message
+    fn within(mut self, parent: &str) -> Self {
+        self.field = format!("{}.{}", parent, self.field);
+        self
+    }
+
+    pub fn at_slot(mut self, slot: Slot) -> Self {
+        self.slot = Some(slot);
+        self
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(slot) = self.slot {
+            write!(f, "slot {}: ", slot)?;
+        }
+        write!(f, "invalid field `{}`: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+fn parse<T>(field: &str, value: &str) -> Result<T, ConversionError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    T::from_str(value).map_err(|e| ConversionError::new(field, format!("{} ({:?})", e, value)))
+}
+
+/// A dumped block converted to Massa types
+pub struct DumpedBlock {
+    pub block: SecureShareBlock,
+    pub operations: Vec<SecureShareOperation>,
+}
+
+impl TryFrom<grpc_model::FilledBlock> for DumpedBlock {
+    type Error = ConversionError;
 
+    fn try_from(f_b: grpc_model::FilledBlock) -> Result<Self, Self::Error> {
+        let slot: Option<Slot> = f_b
+            .header
+            .as_ref()
+            .and_then(|header| header.content.as_ref())
+            .and_then(|content| content.slot.clone())
+            .map(Slot::from);
+
+        let convert = || -> Result<Self, ConversionError> {
+            let operations =
+                secure_shared_operations_from_filled_operation_entries(&f_b.operations)?;
+            let header = f_b
+                .header
+                .ok_or_else(|| ConversionError::missing("header"))?;
+            let block = secure_share_block_from_signed_block_header(
+                header,
+                operations.iter().map(|op| op.id).collect(),
+            )
+            .map_err(|e| e.within("header"))?;
+            Ok(DumpedBlock { block, operations })
+        };
+
+        convert().map_err(|e| match slot {
+            Some(slot) => e.at_slot(slot),
+            None => e,
+        })
+    }
+}
+
+fn secure_share_block_from_signed_block_header(
+    s_bh: grpc_model::SignedBlockHeader,
+    operations: Vec<OperationId>,
+) -> Result<SecureShareBlock, ConversionError> {
+    let content_creator_pub_key: PublicKey =
+        parse("content_creator_pub_key", &s_bh.content_creator_pub_key)?;
+    let signature = parse("signature", &s_bh.signature)?;
+    let content_creator_address = parse("content_creator_address", &s_bh.content_creator_address)?;
 
-pub fn secure_share_block_from_filled_block(f_b: grpc_model::FilledBlock) -> SecureShareBlock {
     let content = Block {
-        header: secure_header_from_signed_block_header(f_b.header.clone().unwrap()),
-        operations: secure_shared_operations_from_filled_operation_entries(&f_b.operations)
-            .iter()
-            .map(|op| op.id)
-            .collect(),
+        header: secure_header_from_signed_block_header(s_bh)?,
+        operations,
     };
     let block_serializer = BlockSerializer::new();
     let mut serialized_data = Vec::new();
     block_serializer
         .serialize(&content, &mut serialized_data)
-        .unwrap();
-
-    let content_creator_pub_key =
-        PublicKey::from_str(&f_b.header.as_ref().unwrap().content_creator_pub_key).unwrap();
+        .map_err(|e| ConversionError::new("block", format!("cannot serialize block: {}", e)))?;
 
-    let hash = content.compute_hash(&serialized_data, &content_creator_pub_key, *CHAINID);
+    let hash = content.compute_hash(
+        &serialized_data,
+        &content_creator_pub_key,
+        network().chain_id,
+    );
 
-    SecureShareBlock {
+    Ok(SecureShareBlock {
         content,
         serialized_data,
-        signature: Signature::from_str(&f_b.header.as_ref().unwrap().signature).unwrap(),
+        signature,
         content_creator_pub_key,
-        content_creator_address: address_from_str(&f_b.header.unwrap().content_creator_address),
+        content_creator_address,
         id: BlockId::new(hash),
-    }
+    })
 }
 
-fn secure_header_from_signed_block_header(s_bh: grpc_model::SignedBlockHeader) -> SecuredHeader {
-    let content = block_header_from_grpc_block_header(s_bh.content.unwrap());
+fn secure_header_from_signed_block_header(
+    s_bh: grpc_model::SignedBlockHeader,
+) -> Result<SecuredHeader, ConversionError> {
+    let content = block_header_from_grpc_block_header(
+        s_bh.content
+            .ok_or_else(|| ConversionError::missing("content"))?,
+    )
+    .map_err(|e| e.within("content"))?;
 
     let header_serializer = BlockHeaderSerializer::new();
     let mut serialized_data = Vec::new();
     header_serializer
         .serialize(&content, &mut serialized_data)
-        .unwrap();
+        .map_err(|e| ConversionError::new("content", format!("cannot serialize header: {}", e)))?;
 
-    let content_creator_pub_key = PublicKey::from_str(&s_bh.content_creator_pub_key).unwrap();
-    let hash = content.compute_hash(&serialized_data, &content_creator_pub_key, *CHAINID);
-    SecuredHeader {
+    let content_creator_pub_key: PublicKey =
+        parse("content_creator_pub_key", &s_bh.content_creator_pub_key)?;
+    let hash = content.compute_hash(
+        &serialized_data,
+        &content_creator_pub_key,
+        network().chain_id,
+    );
+    Ok(SecuredHeader {
         content,
         serialized_data,
-        signature: Signature::from_str(&s_bh.signature).unwrap(),
+        signature: parse("signature", &s_bh.signature)?,
         content_creator_pub_key,
-        content_creator_address: address_from_str(&s_bh.content_creator_address),
+        content_creator_address: parse("content_creator_address", &s_bh.content_creator_address)?,
         id: BlockId::new(hash),
-    }
+    })
 }
-fn block_header_from_grpc_block_header(block_header: grpc_model::BlockHeader) -> BlockHeader {
-    BlockHeader {
+
+fn block_header_from_grpc_block_header(
+    block_header: grpc_model::BlockHeader,
+) -> Result<BlockHeader, ConversionError> {
+    let slot = block_header
+        .slot
+        .ok_or_else(|| ConversionError::missing("slot"))?;
+    let parents = block_header
+        .parents
+        .iter()
+        .enumerate()
+        .map(|(i, bid)| parse(&format!("parents[{}]", i), bid))
+        .collect::<Result<_, _>>()?;
+    let endorsements = endorsements_from_signed_endorsements(&block_header.endorsements)?;
+    let denunciations = block_header
+        .denunciations
+        .into_iter()
+        .enumerate()
+        .map(|(i, denunciation)| {
+            denunciation_from_grpc_denunciation(denunciation)
+                .map_err(|e| e.within(&format!("denunciations[{}]", i)))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(BlockHeader {
         current_version: block_header.current_version,
         announced_version: block_header.announced_version,
-        slot: block_header.slot.unwrap().into(),
-        parents: block_header
-            .parents
-            .iter()
-            .map(|bid| BlockId::from_str(bid).unwrap())
-            .collect(),
-        operation_merkle_root: Hash::from_str(&block_header.operations_hash).unwrap(),
-        endorsements: endorsements_form_signed_endorsements(&block_header.endorsements),
-        // FIXME can't create denunciation from serialized data
-        denunciations: Vec::new(),
+        slot: slot.into(),
+        parents,
+        operation_merkle_root: parse("operations_hash", &block_header.operations_hash)?,
+        endorsements,
+        denunciations,
+    })
+}
+
+fn denunciation_from_grpc_denunciation(
+    g_denun: grpc_model::Denunciation,
+) -> Result<Denunciation, ConversionError> {
+    // Note: the fields of the massa denunciations are private, the denunciation is serialized
+    // and read back with the massa deserializer (as a denunciation received from the network)
+    let u32_serializer = U32VarIntSerializer::new();
+    let slot_serializer = SlotSerializer::new();
+    let mut serialized_data = Vec::new();
+    let serialize_slot = |field: &str, slot: Option<grpc_model::Slot>, buffer: &mut Vec<u8>| {
+        let slot: Slot = slot.ok_or_else(|| ConversionError::missing(field))?.into();
+        slot_serializer
+            .serialize(&slot, buffer)
+            .map_err(|e| ConversionError::new(field, e))
+    };
+    match g_denun
+        .entry
+        .ok_or_else(|| ConversionError::missing("entry"))?
+    {
+        grpc_model::denunciation::Entry::Endorsement(e) => {
+            u32_serializer
+                .serialize(&DENUNCIATION_TYPE_ENDORSEMENT, &mut serialized_data)
+                .map_err(|e| ConversionError::new("endorsement", e))?;
+            serialize_slot("endorsement.slot", e.slot, &mut serialized_data)?;
+            u32_serializer
+                .serialize(&e.index, &mut serialized_data)
+                .map_err(|e| ConversionError::new("endorsement.index", e))?;
+            serialized_data
+                .extend(parse::<PublicKey>("endorsement.public_key", &e.public_key)?.to_bytes());
+            serialized_data.extend(parse::<Hash>("endorsement.hash_1", &e.hash_1)?.to_bytes());
+            serialized_data.extend(parse::<Hash>("endorsement.hash_2", &e.hash_2)?.to_bytes());
+            serialized_data
+                .extend(parse::<Signature>("endorsement.signature_1", &e.signature_1)?.to_bytes());
+            serialized_data
+                .extend(parse::<Signature>("endorsement.signature_2", &e.signature_2)?.to_bytes());
+        }
+        grpc_model::denunciation::Entry::BlockHeader(b) => {
+            u32_serializer
+                .serialize(&DENUNCIATION_TYPE_BLOCK_HEADER, &mut serialized_data)
+                .map_err(|e| ConversionError::new("block_header", e))?;
+            serialize_slot("block_header.slot", b.slot, &mut serialized_data)?;
+            serialized_data
+                .extend(parse::<PublicKey>("block_header.public_key", &b.public_key)?.to_bytes());
+            serialized_data.extend(parse::<Hash>("block_header.hash_1", &b.hash_1)?.to_bytes());
+            serialized_data.extend(parse::<Hash>("block_header.hash_2", &b.hash_2)?.to_bytes());
+            serialized_data
+                .extend(parse::<Signature>("block_header.signature_1", &b.signature_1)?.to_bytes());
+            serialized_data
+                .extend(parse::<Signature>("block_header.signature_2", &b.signature_2)?.to_bytes());
+        }
+    }
+
+    let (rest, denunciation) = DenunciationDeserializer::new(THREAD_COUNT, ENDORSEMENT_COUNT)
+        .deserialize::<DeserializeError>(&serialized_data)
+        .map_err(|e| {
+            ConversionError::new("entry", format!("cannot deserialize denunciation: {}", e))
+        })?;
+    if !rest.is_empty() {
+        return Err(ConversionError::new(
+            "entry",
+            format!("{} trailing bytes after the denunciation", rest.len()),
+        ));
     }
+    Ok(denunciation)
 }
 
-// fn denunciation_from_grpc_denunciation(g_denun: grpc_model::Denunciation) -> Denunciation {
-//     match g_denun.entry.unwrap() {
-//         grpc_model::denunciation::Entry::BlockHeader(b) => {
-//             Denunciation::BlockHeader(BlockHeaderDenunciation {
-//                 public_key: PublicKey::from_str(b.public_key).unwrap(),
-//                 slot: b.slot,
-//                 hash_1: b.hash_1,
-//                 hash_2: b.hash_2,
-//                 signature_1: b.signature_1,
-//                 signature_2: b.signature_2,
-//             })
-//         }
-//         grpc_model::denunciation::Entry::Endorsement(e) => {
-//             Denunciation::Endorsement(EndorsementDenunciation {
-//                 public_key: todo!(),
-//                 slot: todo!(),
-//                 index: todo!(),
-//                 hash_1: todo!(),
-//                 hash_2: todo!(),
-//                 signature_1: todo!(),
-//                 signature_2: todo!(),
-//             })
-//         }
-//     }
-// }
-
-fn endorsements_form_signed_endorsements(
+fn endorsements_from_signed_endorsements(
     endorsements: &[grpc_model::SignedEndorsement],
-) -> Vec<SecureShare<Endorsement, EndorsementId>> {
+) -> Result<Vec<SecureShare<Endorsement, EndorsementId>>, ConversionError> {
     endorsements
-        .into_iter()
-        .map(|s_endo| secure_share_endorsement_from_signed_endorsement(s_endo.to_owned()))
+        .iter()
+        .enumerate()
+        .map(|(i, s_endo)| {
+            secure_share_endorsement_from_signed_endorsement(s_endo.to_owned())
+                .map_err(|e| e.within(&format!("endorsements[{}]", i)))
+        })
         .collect()
 }
 
 fn secure_share_endorsement_from_signed_endorsement(
     s_endo: grpc_model::SignedEndorsement,
-) -> SecureShareEndorsement {
-    let content = s_endo.content.unwrap();
+) -> Result<SecureShareEndorsement, ConversionError> {
+    let content = s_endo
+        .content
+        .ok_or_else(|| ConversionError::missing("content"))?;
 
     let content = Endorsement {
-        slot: content.slot.unwrap().into(),
+        slot: content
+            .slot
+            .ok_or_else(|| ConversionError::missing("content.slot"))?
+            .into(),
         index: content.index,
-        endorsed_block: BlockId::from_str(&content.endorsed_block).unwrap()
+        endorsed_block: parse("content.endorsed_block", &content.endorsed_block)?,
     };
 
     let endo_serializer = EndorsementSerializer::new();
     let mut serialized_data = Vec::new();
     endo_serializer
         .serialize(&content, &mut serialized_data)
-        .expect("Failed to serialize endorsement");
+        .map_err(|e| {
+            ConversionError::new("content", format!("cannot serialize endorsement: {}", e))
+        })?;
 
-    let content_creator_pub_key = PublicKey::from_str(&s_endo.content_creator_pub_key).unwrap();
-    let hash = content.compute_hash(&serialized_data, &content_creator_pub_key, *CHAINID);
+    let content_creator_pub_key: PublicKey =
+        parse("content_creator_pub_key", &s_endo.content_creator_pub_key)?;
+    let hash = content.compute_hash(
+        &serialized_data,
+        &content_creator_pub_key,
+        network().chain_id,
+    );
 
-    SecureShareEndorsement {
+    Ok(SecureShareEndorsement {
         content,
         serialized_data,
-        signature: Signature::from_str(&s_endo.signature).unwrap(),
+        signature: parse("signature", &s_endo.signature)?,
         content_creator_pub_key,
-        content_creator_address: address_from_str(&s_endo.content_creator_address),
+        content_creator_address: parse("content_creator_address", &s_endo.content_creator_address)?,
         id: EndorsementId::new(hash),
-    }
+    })
 }
 
-pub fn secure_shared_operations_from_filled_operation_entries(
+fn secure_shared_operations_from_filled_operation_entries(
     operations: &[grpc_model::FilledOperationEntry],
-) -> Vec<SecureShare<Operation, OperationId>> {
+) -> Result<Vec<SecureShare<Operation, OperationId>>, ConversionError> {
     operations
-        .into_iter()
-        .map(
-            |grpc_model::FilledOperationEntry {
-                 operation_id: _,
-                 operation,
-             }| {
-                match operation {
-                    Some(op) => {
-                        secure_share_operation_from_signed_operation(op.clone())
-                    }
-                    None => {
-                        // TODO: panic if op is None?
-                        todo!()
-                    }
-                }
-            },
-        )
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            entry
+                .operation
+                .clone()
+                .ok_or_else(|| ConversionError::missing("operation"))
+                .and_then(secure_share_operation_from_signed_operation)
+                .map_err(|e| e.within(&format!("operations[{}]", i)))
+        })
         .collect()
 }
 
 fn secure_share_operation_from_signed_operation(
     s_op: grpc_model::SignedOperation,
-) -> SecureShareOperation {
-    let content: grpc_model::Operation = s_op.content.expect("Missing operation content");
-    let fee = content.fee.expect("Missing operation fee");
+) -> Result<SecureShareOperation, ConversionError> {
+    let content: grpc_model::Operation = s_op
+        .content
+        .ok_or_else(|| ConversionError::missing("content"))?;
+    let fee = content
+        .fee
+        .ok_or_else(|| ConversionError::missing("content.fee"))?;
 
-    let fee = amount_from_native_amount(fee);
+    let fee = amount_from_native_amount("content.fee", fee)?;
 
     let op: OperationType = match content.op {
         Some(op_type) => match op_type.r#type {
-            Some(op_type) => operation_type_from_op_type(op_type),
-
-            None => panic!("Missing operation type"),
+            Some(op_type) => {
+                operation_type_from_op_type(op_type).map_err(|e| e.within("content.op.type"))?
+            }
+            None => return Err(ConversionError::missing("content.op.type")),
         },
-        None => panic!("Missing operation details"),
+        None => return Err(ConversionError::missing("content.op")),
     };
 
     let op: Operation = Operation {
@@ -197,27 +389,44 @@ fn secure_share_operation_from_signed_operation(
     let mut serialized_data = Vec::new();
     op_serializer
         .serialize(&op, &mut serialized_data)
-        .expect("Failed to serialize operation");
+        .map_err(|e| {
+            ConversionError::new("content", format!("cannot serialize operation: {}", e))
+        })?;
 
-    let content_creator_pub_key = PublicKey::from_str(&s_op.content_creator_pub_key).unwrap();
+    let content_creator_pub_key: PublicKey =
+        parse("content_creator_pub_key", &s_op.content_creator_pub_key)?;
 
-    let hash = op.compute_hash(&serialized_data, &content_creator_pub_key, *CHAINID);
+    let hash = op.compute_hash(
+        &serialized_data,
+        &content_creator_pub_key,
+        network().chain_id,
+    );
 
-    SecureShareOperation {
+    Ok(SecureShareOperation {
         content: op,
         serialized_data,
-        signature: Signature::from_str(&s_op.signature).unwrap(),
+        signature: parse("signature", &s_op.signature)?,
         content_creator_pub_key,
-        content_creator_address: address_from_str(&s_op.content_creator_address),
+        content_creator_address: parse("content_creator_address", &s_op.content_creator_address)?,
         id: OperationId::new(hash),
-    }
+    })
 }
 
-fn operation_type_from_op_type(op_type: grpc_model::operation_type::Type) -> OperationType {
-    match op_type {
+fn operation_type_from_op_type(
+    op_type: grpc_model::operation_type::Type,
+) -> Result<OperationType, ConversionError> {
+    Ok(match op_type {
         grpc_model::operation_type::Type::Transaction(transaction) => OperationType::Transaction {
-            recipient_address: address_from_str(&transaction.recipient_address),
-            amount: amount_from_native_amount(transaction.amount.unwrap()),
+            recipient_address: parse(
+                "transaction.recipient_address",
+                &transaction.recipient_address,
+            )?,
+            amount: amount_from_native_amount(
+                "transaction.amount",
+                transaction
+                    .amount
+                    .ok_or_else(|| ConversionError::missing("transaction.amount"))?,
+            )?,
         },
         grpc_model::operation_type::Type::RollBuy(roll_buy) => OperationType::RollBuy {
             roll_count: roll_buy.roll_count,
@@ -226,9 +435,9 @@ fn operation_type_from_op_type(op_type: grpc_model::operation_type::Type) -> Ope
             roll_count: roll_sell.roll_count,
         },
         grpc_model::operation_type::Type::ExecutSc(execute_sc) => OperationType::ExecuteSC {
+            max_coins: amount_from_raw("execut_sc.max_coins", execute_sc.max_coins)?,
             data: execute_sc.data,
             max_gas: execute_sc.max_gas,
-            max_coins: amount_from_raw(execute_sc.max_coins),
             datastore: execute_sc
                 .datastore
                 .into_iter()
@@ -236,31 +445,477 @@ fn operation_type_from_op_type(op_type: grpc_model::operation_type::Type) -> Ope
                 .collect(),
         },
         grpc_model::operation_type::Type::CallSc(call_sc) => OperationType::CallSC {
-            target_addr: address_from_str(&call_sc.target_address),
+            target_addr: parse("call_sc.target_address", &call_sc.target_address)?,
+            coins: amount_from_native_amount(
+                "call_sc.coins",
+                call_sc
+                    .coins
+                    .ok_or_else(|| ConversionError::missing("call_sc.coins"))?,
+            )?,
             target_func: call_sc.target_function,
             param: call_sc.parameter,
             max_gas: call_sc.max_gas,
-            coins: amount_from_native_amount(call_sc.coins.unwrap()),
         },
+    })
+}
+
+fn amount_from_native_amount(
+    field: &str,
+    na: grpc_model::NativeAmount,
+) -> Result<Amount, ConversionError> {
+    Amount::from_mantissa_scale(na.mantissa, na.scale).map_err(|_| {
+        ConversionError::new(
+            field,
+            format!(
+                "cannot convert mantissa {} and scale {} to Amount",
+                na.mantissa, na.scale
+            ),
+        )
+    })
+}
+
+fn amount_from_raw(field: &str, raw: u64) -> Result<Amount, ConversionError> {
+    amount_from_native_amount(
+        field,
+        grpc_model::NativeAmount {
+            mantissa: raw,
+            scale: AMOUNT_DECIMAL_SCALE,
+        },
+    )
+}
+
+impl DumpedBlock {
+    /// Gather the operations of a block from the storage
+    pub fn from_storage(
+        block: SecureShareBlock,
+        storage: &Storage,
+    ) -> Result<Self, ConversionError> {
+        let stored_operations = storage.read_operations();
+        let operations = block
+            .content
+            .operations
+            .iter()
+            .enumerate()
+            .map(|(i, op_id)| {
+                stored_operations.get(op_id).cloned().ok_or_else(|| {
+                    ConversionError::new(
+                        &format!("operations[{}]", i),
+                        format!("operation {} not in storage", op_id),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.at_slot(block.content.header.content.slot))?;
+        Ok(DumpedBlock { block, operations })
     }
 }
 
-pub fn address_from_str(addr: &str) -> Address {
-    Address::from_str(addr).unwrap_or_else(|_| panic!("Failed to parse Address from string"))
+/// Reverse conversion, to write dumps of blocks built (or modified) in Rust
+impl From<&DumpedBlock> for grpc_model::FilledBlock {
+    fn from(dumped_block: &DumpedBlock) -> Self {
+        grpc_model::FilledBlock {
+            header: Some(signed_block_header_from_secured_header(
+                &dumped_block.block.content.header,
+            )),
+            operations: dumped_block
+                .operations
+                .iter()
+                .map(|op| grpc_model::FilledOperationEntry {
+                    operation_id: op.id.to_string(),
+                    operation: Some(signed_operation_from_secure_share_operation(op)),
+                })
+                .collect(),
+        }
+    }
 }
 
-fn amount_from_native_amount(na: grpc_model::NativeAmount) -> Amount {
-    Amount::from_mantissa_scale(na.mantissa, na.scale).unwrap_or_else(|_| {
-        panic!(
-            "Failed to convent mantissa {} and scale {} to Amount",
-            na.mantissa, na.scale
-        )
-    })
+fn grpc_slot(slot: Slot) -> grpc_model::Slot {
+    grpc_model::Slot {
+        period: slot.period,
+        thread: slot.thread as u32,
+    }
 }
 
-fn amount_from_raw(raw: u64) -> Amount {
-    amount_from_native_amount(grpc_model::NativeAmount {
-        mantissa: raw,
+fn native_amount_from_amount(amount: Amount) -> grpc_model::NativeAmount {
+    grpc_model::NativeAmount {
+        mantissa: amount.to_raw(),
         scale: AMOUNT_DECIMAL_SCALE,
-    })
-}
\ No newline at end of file
+    }
+}
+
+fn signed_block_header_from_secured_header(
+    header: &SecuredHeader,
+) -> grpc_model::SignedBlockHeader {
+    let content = &header.content;
+    grpc_model::SignedBlockHeader {
+        content: Some(grpc_model::BlockHeader {
+            current_version: content.current_version,
+            announced_version: content.announced_version,
+            slot: Some(grpc_slot(content.slot)),
+            parents: content.parents.iter().map(|p| p.to_string()).collect(),
+            operations_hash: content.operation_merkle_root.to_string(),
+            endorsements: content
+                .endorsements
+                .iter()
+                .map(signed_endorsement_from_secure_share_endorsement)
+                .collect(),
+            denunciations: content
+                .denunciations
+                .iter()
+                .map(grpc_denunciation_from_denunciation)
+                .collect(),
+        }),
+        signature: header.signature.to_string(),
+        content_creator_pub_key: header.content_creator_pub_key.to_string(),
+        content_creator_address: header.content_creator_address.to_string(),
+        secure_hash: header.id.to_string(),
+        serialized_size: header.serialized_data.len() as u64,
+    }
+}
+
+fn signed_endorsement_from_secure_share_endorsement(
+    endorsement: &SecureShareEndorsement,
+) -> grpc_model::SignedEndorsement {
+    grpc_model::SignedEndorsement {
+        content: Some(grpc_model::Endorsement {
+            slot: Some(grpc_slot(endorsement.content.slot)),
+            index: endorsement.content.index,
+            endorsed_block: endorsement.content.endorsed_block.to_string(),
+        }),
+        signature: endorsement.signature.to_string(),
+        content_creator_pub_key: endorsement.content_creator_pub_key.to_string(),
+        content_creator_address: endorsement.content_creator_address.to_string(),
+        secure_hash: endorsement.id.to_string(),
+        serialized_size: endorsement.serialized_data.len() as u64,
+    }
+}
+
+fn grpc_denunciation_from_denunciation(denunciation: &Denunciation) -> grpc_model::Denunciation {
+    // Note: the fields of the massa denunciations are private, use the massa conversion
+    // (built from the typed fields, as served by the node gRPC API)
+    grpc_model::Denunciation::from(denunciation.clone())
+}
+
+fn signed_operation_from_secure_share_operation(
+    op: &SecureShareOperation,
+) -> grpc_model::SignedOperation {
+    grpc_model::SignedOperation {
+        content: Some(grpc_model::Operation {
+            fee: Some(native_amount_from_amount(op.content.fee)),
+            expire_period: op.content.expire_period,
+            op: Some(grpc_model::OperationType {
+                r#type: Some(op_type_from_operation_type(&op.content.op)),
+            }),
+        }),
+        signature: op.signature.to_string(),
+        content_creator_pub_key: op.content_creator_pub_key.to_string(),
+        content_creator_address: op.content_creator_address.to_string(),
+        secure_hash: op.id.to_string(),
+        serialized_size: op.serialized_data.len() as u64,
+    }
+}
+
+fn op_type_from_operation_type(op: &OperationType) -> grpc_model::operation_type::Type {
+    match op {
+        OperationType::Transaction {
+            recipient_address,
+            amount,
+        } => grpc_model::operation_type::Type::Transaction(grpc_model::Transaction {
+            recipient_address: recipient_address.to_string(),
+            amount: Some(native_amount_from_amount(*amount)),
+        }),
+        OperationType::RollBuy { roll_count } => {
+            grpc_model::operation_type::Type::RollBuy(grpc_model::RollBuy {
+                roll_count: *roll_count,
+            })
+        }
+        OperationType::RollSell { roll_count } => {
+            grpc_model::operation_type::Type::RollSell(grpc_model::RollSell {
+                roll_count: *roll_count,
+            })
+        }
+        OperationType::ExecuteSC {
+            data,
+            max_gas,
+            max_coins,
+            datastore,
+        } => grpc_model::operation_type::Type::ExecutSc(grpc_model::ExecuteSc {
+            data: data.clone(),
+            max_gas: *max_gas,
+            max_coins: max_coins.to_raw(),
+            datastore: datastore
+                .iter()
+                .map(|(key, value)| grpc_model::BytesMapFieldEntry {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        }),
+        OperationType::CallSC {
+            target_addr,
+            target_func,
+            param,
+            max_gas,
+            coins,
+        } => grpc_model::operation_type::Type::CallSc(grpc_model::CallSc {
+            target_address: target_addr.to_string(),
+            target_function: target_func.clone(),
+            parameter: param.clone(),
+            max_gas: *max_gas,
+            coins: Some(native_amount_from_amount(*coins)),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use massa_models::address::Address;
+    use massa_models::block_header::BlockHeader;
+    use massa_signature::KeyPair;
+
+    use super::*;
+    use crate::config::init_test_network;
+
+    fn block_id(seed: u8) -> BlockId {
+        BlockId::new(Hash::compute_from(&[seed]))
+    }
+
+    fn operation(keypair: &KeyPair, op: OperationType) -> SecureShareOperation {
+        let operation = Operation {
+            fee: Amount::from_str("0.01").unwrap(),
+            expire_period: 1000,
+            op,
+        };
+        Operation::new_verifiable(
+            operation,
+            OperationSerializer::new(),
+            keypair,
+            network().chain_id,
+        )
+        .unwrap()
+    }
+
+    fn endorsement(
+        keypair: &KeyPair,
+        slot: Slot,
+        endorsed_block: BlockId,
+    ) -> SecureShareEndorsement {
+        let endorsement = Endorsement {
+            slot,
+            index: 3,
+            endorsed_block,
+        };
+        Endorsement::new_verifiable(
+            endorsement,
+            EndorsementSerializer::new(),
+            keypair,
+            network().chain_id,
+        )
+        .unwrap()
+    }
+
+    fn header(
+        keypair: &KeyPair,
+        slot: Slot,
+        operation_merkle_root: Hash,
+        endorsements: Vec<SecureShareEndorsement>,
+        denunciations: Vec<Denunciation>,
+    ) -> SecuredHeader {
+        let header = BlockHeader {
+            current_version: 0,
+            announced_version: None,
+            slot,
+            parents: (0..THREAD_COUNT).map(block_id).collect(),
+            operation_merkle_root,
+            endorsements,
+            denunciations,
+        };
+        BlockHeader::new_verifiable(
+            header,
+            BlockHeaderSerializer::new(),
+            keypair,
+            network().chain_id,
+        )
+        .unwrap()
+    }
+
+    fn dumped_block(
+        keypair: &KeyPair,
+        header: SecuredHeader,
+        operations: Vec<SecureShareOperation>,
+    ) -> DumpedBlock {
+        let block = Block {
+            header,
+            operations: operations.iter().map(|op| op.id).collect(),
+        };
+        let block =
+            Block::new_verifiable(block, BlockSerializer::new(), keypair, network().chain_id)
+                .unwrap();
+        DumpedBlock { block, operations }
+    }
+
+    /// Convert the block to gRPC types and back, the result must be the same block
+    fn assert_round_trip(dumped_block: &DumpedBlock) -> DumpedBlock {
+        let round_trip = DumpedBlock::try_from(grpc_model::FilledBlock::from(dumped_block))
+            .unwrap_or_else(|e| panic!("round trip conversion failed: {}", e));
+        assert_eq!(round_trip.block.id, dumped_block.block.id);
+        assert_eq!(
+            round_trip.block.serialized_data,
+            dumped_block.block.serialized_data
+        );
+        assert_eq!(
+            round_trip.block.content.header.id,
+            dumped_block.block.content.header.id
+        );
+        assert_eq!(round_trip.operations.len(), dumped_block.operations.len());
+        for (op, expected) in round_trip
+            .operations
+            .iter()
+            .zip(dumped_block.operations.iter())
+        {
+            assert_eq!(op.id, expected.id);
+            assert_eq!(op.serialized_data, expected.serialized_data);
+            assert_eq!(op.signature, expected.signature);
+        }
+        round_trip
+    }
+
+    #[test]
+    fn operations_round_trip() {
+        init_test_network();
+        let keypair = KeyPair::generate(0).unwrap();
+        let address = Address::from_public_key(&keypair.get_public_key());
+        let operations = vec![
+            operation(
+                &keypair,
+                OperationType::Transaction {
+                    recipient_address: address,
+                    amount: Amount::from_str("1.5").unwrap(),
+                },
+            ),
+            operation(&keypair, OperationType::RollBuy { roll_count: 3 }),
+            operation(&keypair, OperationType::RollSell { roll_count: 2 }),
+            operation(
+                &keypair,
+                OperationType::ExecuteSC {
+                    data: vec![1, 2, 3],
+                    max_gas: 1_000_000,
+                    max_coins: Amount::from_str("0.1").unwrap(),
+                    datastore: BTreeMap::from([(vec![1], vec![2]), (vec![3], vec![])]),
+                },
+            ),
+            operation(
+                &keypair,
+                OperationType::CallSC {
+                    target_addr: address,
+                    target_func: "transfer".to_string(),
+                    param: vec![4, 5],
+                    max_gas: 500_000,
+                    coins: Amount::from_str("2").unwrap(),
+                },
+            ),
+        ];
+
+        let slot = Slot::new(10, 1);
+        let endorsements = vec![endorsement(&keypair, slot, block_id(1))];
+        let header = header(
+            &keypair,
+            slot,
+            Hash::compute_from(b"ops"),
+            endorsements,
+            vec![],
+        );
+        let round_trip = assert_round_trip(&dumped_block(&keypair, header, operations));
+        assert_eq!(
+            round_trip.block.content.header.content.endorsements.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn endorsement_denunciation_round_trip() {
+        init_test_network();
+        let keypair = KeyPair::generate(0).unwrap();
+        let denounced_slot = Slot::new(9, 4);
+        let denunciation = Denunciation::try_from((
+            &endorsement(&keypair, denounced_slot, block_id(1)),
+            &endorsement(&keypair, denounced_slot, block_id(2)),
+        ))
+        .unwrap();
+
+        let header = header(
+            &keypair,
+            Slot::new(10, 0),
+            Hash::compute_from(b"ops"),
+            vec![],
+            vec![denunciation.clone()],
+        );
+        let round_trip = assert_round_trip(&dumped_block(&keypair, header, vec![]));
+        assert_eq!(
+            round_trip.block.content.header.content.denunciations,
+            vec![denunciation]
+        );
+    }
+
+    #[test]
+    fn block_header_denunciation_round_trip() {
+        init_test_network();
+        let keypair = KeyPair::generate(0).unwrap();
+        let denounced_slot = Slot::new(9, 4);
+        let denunciation = Denunciation::try_from((
+            &header(
+                &keypair,
+                denounced_slot,
+                Hash::compute_from(b"a"),
+                vec![],
+                vec![],
+            ),
+            &header(
+                &keypair,
+                denounced_slot,
+                Hash::compute_from(b"b"),
+                vec![],
+                vec![],
+            ),
+        ))
+        .unwrap();
+
+        let header = header(
+            &keypair,
+            Slot::new(10, 0),
+            Hash::compute_from(b"ops"),
+            vec![],
+            vec![denunciation.clone()],
+        );
+        let round_trip = assert_round_trip(&dumped_block(&keypair, header, vec![]));
+        assert_eq!(
+            round_trip.block.content.header.content.denunciations,
+            vec![denunciation]
+        );
+    }
+
+    #[test]
+    fn invalid_field_is_named() {
+        init_test_network();
+        let keypair = KeyPair::generate(0).unwrap();
+        let slot = Slot::new(10, 1);
+        let header = header(&keypair, slot, Hash::compute_from(b"ops"), vec![], vec![]);
+        let operations = vec![operation(
+            &keypair,
+            OperationType::RollBuy { roll_count: 1 },
+        )];
+        let mut filled_block =
+            grpc_model::FilledBlock::from(&dumped_block(&keypair, header, operations));
+        filled_block.operations[0]
+            .operation
+            .as_mut()
+            .unwrap()
+            .content_creator_pub_key = "invalid".to_string();
+
+        let e = DumpedBlock::try_from(filled_block).err().unwrap();
+        assert_eq!(e.slot, Some(slot));
+        assert_eq!(e.field, "operations[0].content_creator_pub_key");
+    }
+}
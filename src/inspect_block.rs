@@ -0,0 +1,209 @@
+use clap::ValueEnum;
+use massa_models::slot::Slot;
+use massa_proto_rs::massa::model::v1::{self as grpc_model};
+use prost::Message;
+use serde_json::{json, Value};
+
+use crate::args::InspectBlockArgs;
+use crate::block_storage::BlockStorage;
+use crate::grpc_conv::{ConversionError, DumpedBlock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InspectFormat {
+    Json,
+    Table,
+}
+
+/// Recomputed id next to the id stored in the dump
+fn id_to_json(recomputed: String, stored: &str) -> Value {
+    json!({
+        "id": recomputed,
+        "stored_id": stored,
+        "id_match": recomputed == stored,
+    })
+}
+
+fn block_to_json(filled_block: grpc_model::FilledBlock) -> Result<Value, ConversionError> {
+    let signed_header = filled_block.header.clone().unwrap_or_default();
+    let stored_endorsement_ids: Vec<String> = signed_header
+        .content
+        .as_ref()
+        .map(|content| {
+            content
+                .endorsements
+                .iter()
+                .map(|e| e.secure_hash.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+    let stored_operation_ids: Vec<String> = filled_block
+        .operations
+        .iter()
+        .map(|entry| entry.operation_id.clone())
+        .collect();
+    let DumpedBlock { block, operations } = DumpedBlock::try_from(filled_block)?;
+    let header = &block.content.header.content;
+
+    Ok(json!({
+        "block": id_to_json(block.id.to_string(), &signed_header.secure_hash),
+        "header": {
+            "slot": header.slot.to_string(),
+            "creator": block.content_creator_address.to_string(),
+            "current_version": header.current_version,
+            "announced_version": header.announced_version,
+            "parents": header.parents.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            "operation_merkle_root": header.operation_merkle_root.to_string(),
+            "denunciations": header.denunciations.len(),
+        },
+        "endorsements": header
+            .endorsements
+            .iter()
+            .zip(stored_endorsement_ids.iter())
+            .map(|(endorsement, stored_id)| {
+                let mut value = id_to_json(endorsement.id.to_string(), stored_id);
+                value["index"] = json!(endorsement.content.index);
+                value["slot"] = json!(endorsement.content.slot.to_string());
+                value["endorsed_block"] = json!(endorsement.content.endorsed_block.to_string());
+                value["creator"] = json!(endorsement.content_creator_address.to_string());
+                value
+            })
+            .collect::<Vec<_>>(),
+        "operations": operations
+            .iter()
+            .zip(stored_operation_ids.iter())
+            .map(|(operation, stored_id)| {
+                let mut value = id_to_json(operation.id.to_string(), stored_id);
+                value["creator"] = json!(operation.content_creator_address.to_string());
+                value["fee"] = json!(operation.content.fee.to_string());
+                value["expire_period"] = json!(operation.content.expire_period);
+                value["op"] = json!(format!("{:?}", operation.content.op));
+                value
+            })
+            .collect::<Vec<_>>(),
+    }))
+}
+
+fn mismatch(value: &Value) -> &'static str {
+    if value["id_match"].as_bool().unwrap_or(false) {
+        ""
+    } else {
+        " (MISMATCH)"
+    }
+}
+
+fn print_table(block: &Value) {
+    let header = &block["header"];
+    println!(
+        "Block {} (stored: {}){}",
+        block["block"]["id"].as_str().unwrap_or_default(),
+        block["block"]["stored_id"].as_str().unwrap_or_default(),
+        mismatch(&block["block"])
+    );
+    for field in [
+        "slot",
+        "creator",
+        "current_version",
+        "announced_version",
+        "operation_merkle_root",
+        "denunciations",
+    ] {
+        println!("  {:<22} {}", field, header[field]);
+    }
+    for parent in header["parents"].as_array().into_iter().flatten() {
+        println!("  {:<22} {}", "parent", parent.as_str().unwrap_or_default());
+    }
+
+    let endorsements = block["endorsements"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    println!("Endorsements ({}):", endorsements.len());
+    println!(
+        "  {:<5} {:<55} {:<55} {}",
+        "index", "id", "creator", "endorsed block"
+    );
+    for e in endorsements.iter() {
+        println!(
+            "  {:<5} {:<55} {:<55} {}{}",
+            e["index"],
+            e["id"].as_str().unwrap_or_default(),
+            e["creator"].as_str().unwrap_or_default(),
+            e["endorsed_block"].as_str().unwrap_or_default(),
+            mismatch(e)
+        );
+        if e["id_match"].as_bool() == Some(false) {
+            println!(
+                "        stored id: {}",
+                e["stored_id"].as_str().unwrap_or_default()
+            );
+        }
+    }
+
+    let operations = block["operations"].as_array().cloned().unwrap_or_default();
+    println!("Operations ({}):", operations.len());
+    println!(
+        "  {:<55} {:<55} {:<12} {:<8} {}",
+        "id", "creator", "fee", "expire", "op"
+    );
+    for op in operations.iter() {
+        println!(
+            "  {:<55} {:<55} {:<12} {:<8} {}{}",
+            op["id"].as_str().unwrap_or_default(),
+            op["creator"].as_str().unwrap_or_default(),
+            op["fee"].as_str().unwrap_or_default(),
+            op["expire_period"],
+            op["op"].as_str().unwrap_or_default(),
+            mismatch(op)
+        );
+        if op["id_match"].as_bool() == Some(false) {
+            println!(
+                "  stored id: {}",
+                op["stored_id"].as_str().unwrap_or_default()
+            );
+        }
+    }
+}
+
+/// Decode a dumped block and print it (with the recomputed ids next to the stored ones)
+pub fn inspect_block(args: &InspectBlockArgs) {
+    let slot = Slot::new(args.slot.0, args.slot.1);
+    let format = args
+        .blocks_format
+        .resolve(&args.dump_block_path)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let block_db = BlockStorage::open(&args.dump_block_path, format).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let content = match block_db.read(&slot) {
+        Some(Ok(content)) => content,
+        Some(Err(e)) => {
+            eprintln!("Dumped block of slot {}: {}", slot, e);
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!("No dumped block for slot {}", slot);
+            std::process::exit(1);
+        }
+    };
+    let filled_block = grpc_model::FilledBlock::decode(&content[..]).unwrap_or_else(|e| {
+        eprintln!("Dumped block of slot {} cannot be decoded: {}", slot, e);
+        std::process::exit(1);
+    });
+
+    let block = block_to_json(filled_block).unwrap_or_else(|e| {
+        eprintln!("Dumped block cannot be converted, {}", e);
+        std::process::exit(1);
+    });
+    match args.format {
+        InspectFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&block).expect("Failed to serialize block")
+        ),
+        InspectFormat::Table => print_table(&block),
+    }
+}
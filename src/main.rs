@@ -1,44 +1,30 @@
 // std
+use std::cell::RefCell;
+use std::fs::{create_dir_all, remove_dir_all};
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use std::{collections::HashMap, path::Path, path::PathBuf, thread};
+use std::{collections::BTreeSet, collections::HashMap, path::Path, path::PathBuf};
 
 // third party crates
-use cfg_if::cfg_if;
 use clap::Parser;
 use copy_dir::copy_dir;
-use glob::glob;
 use parking_lot::RwLock;
-use prost::Message;
-#[cfg(feature = "db_storage_backend")]
-use rocksdb::{IteratorMode, DB};
 use tokio::sync::broadcast;
 
 // Massa crates
 use massa_db_exports::MassaDBController;
 use massa_execution_exports::{ExecutionBlockMetadata, ExecutionChannels};
 use massa_execution_worker::start_execution_worker;
-#[cfg(feature = "file_storage_backend")]
-use massa_execution_worker::storage_backend::{FileStorageBackend, StorageBackend};
-#[cfg(feature = "db_storage_backend")]
-use massa_execution_worker::storage_backend::{RocksDBStorageBackend, StorageBackend};
 use massa_final_state::{FinalState, FinalStateController};
 use massa_ledger_worker::FinalLedger;
 use massa_metrics::MassaMetrics;
-#[cfg(feature = "db_storage_backend")]
-use massa_models::slot::SLOT_KEY_SIZE;
 use massa_models::{
-    block::SecureShareBlock,
-    config::{CHAINID, THREAD_COUNT},
-    prehash::PreHashMap,
-    slot::Slot,
+    address::Address, block_id::BlockId, config::THREAD_COUNT, prehash::PreHashMap, slot::Slot,
 };
 use massa_pos_worker::start_selector_worker;
-use massa_proto_rs::massa::model::v1::{self as grpc_model};
 use massa_storage::Storage;
-use massa_time::MassaTime;
 use massa_versioning::versioning::MipStore;
 use massa_wallet::Wallet;
 use tracing::metadata::LevelFilter;
@@ -48,23 +34,69 @@ use tracing_subscriber::util::SubscriberInitExt;
 
 // Custom code
 use crate::args::{Cli, Commands, ReplayArgs};
+use crate::bisect::bisect;
+use crate::block_storage::{replay_end, BadBlockPolicy, BlockStorage};
+use crate::blocks_coverage::blocks_coverage;
+use crate::compare_gas::compare_gas;
 use crate::config::{
-    get_db_config, get_execution_config, get_final_state_config, get_ledger_config,
-    get_mip_stats_config, get_selector_config,
+    check_gas_costs_folder, get_db_config, get_execution_config, get_final_state_config,
+    get_ledger_config, get_mip_stats_config, get_selector_config, init_config, network,
+    node_settings,
 };
-use crate::grpc_conv::{
-    address_from_str, secure_share_block_from_filled_block,
-    secure_shared_operations_from_filled_operation_entries,
+use crate::convert_blocks::convert_blocks;
+use crate::digest::compare_digests;
+use crate::grpc_conv::{ConversionError, DumpedBlock};
+use crate::inspect_block::inspect_block;
+use crate::module_cache::precompile_modules;
+use crate::run_manifest::{resume, Workdir};
+use crate::slot_execution::SlotExecutionListener;
+use crate::slot_handler::FinalizedSlotHandler;
+use crate::snapshot::{
+    list_snapshots, read_snapshot, select_snapshot, snapshot_pattern, SnapshotChecker,
+    SnapshotWriter,
 };
+use crate::sweep::sweep;
+#[cfg(feature = "execution-trace")]
+use crate::trace_sink::{TraceFilter, TraceSink};
+use crate::verify_blocks::{report_failures, verify_blocks, verify_blocks_command};
 use crate::wrapped_massa_db::WrappedMassaDB;
 
 mod args;
+mod async_message_tracker;
+mod bisect;
+mod block_index;
+mod block_storage;
 mod block_storer;
+mod blocks_coverage;
+mod compare_gas;
 mod config;
+mod convert_blocks;
+mod digest;
+mod gas_meter;
 mod grpc_conv;
+mod inspect_block;
+mod module_cache;
+mod perf;
+mod run_manifest;
+mod slot_execution;
+mod slot_handler;
+mod slot_output_writer;
+mod snapshot;
+mod sweep;
+#[cfg(feature = "execution-trace")]
+mod trace_sink;
+mod verify_blocks;
 mod wrapped_massa_db;
 
-const EXECUTION_TIME_PER_BLOCK_IN_S: u64 = 2;
+/// Max number of periods to look back for the same thread parent of a block
+const MAX_PARENT_LOOKBACK_PERIODS: u64 = 64;
+
+/// Exit code used when the replayed state diverges from a reference
+pub(crate) const EXIT_DIVERGENCE: i32 = 2;
+
+/// Max time to wait for the traces of the last slot once it is finalized
+#[cfg(feature = "execution-trace")]
+const TRACE_SINK_GRACE_PERIOD: Duration = Duration::from_secs(10);
 
 fn main() {
     // init env
@@ -73,32 +105,111 @@ fn main() {
 
     // Parse command line arguments
     let cli = Cli::parse();
+    cli.check_required_args();
+    // a resumed replay uses the network of its manifest
+    if !matches!(&cli.command, Commands::Replay(args) if args.resume.is_some()) {
+        init_config(&cli);
+    }
 
-    match cli.command {
-        Commands::ListSnapshot => list_snapshot(&cli.db_path),
-        Commands::Replay(replay_args) => replay(&cli.initial_rolls_path, &replay_args),
+    match &cli.command {
+        Commands::ListSnapshot => list_snapshot(cli.db_path()),
+        Commands::Replay(replay_args) => match replay_args.resume.as_ref() {
+            Some(workdir) => resume(workdir),
+            None => replay(
+                cli.db_path(),
+                cli.initial_rolls_path(),
+                replay_args,
+                std::env::args().collect(),
+            ),
+        },
+        Commands::CompareDigests(args) => {
+            if !compare_digests(&args.digest_a, &args.digest_b) {
+                std::process::exit(EXIT_DIVERGENCE);
+            }
+        }
+        Commands::Bisect(args) => bisect(cli.db_path(), cli.initial_rolls_path(), args),
+        Commands::ConvertBlocks(args) => convert_blocks(args),
+        Commands::BlocksCoverage(args) => blocks_coverage(cli.db_path.as_deref(), args),
+        Commands::InspectBlock(args) => inspect_block(args),
+        Commands::VerifyBlocks(args) => verify_blocks_command(args),
+        Commands::CompareGas(args) => compare_gas(cli.db_path(), cli.initial_rolls_path(), args),
+        Commands::Sweep(args) => sweep(cli.db_path(), cli.initial_rolls_path(), args),
     }
 }
 
-fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
+fn replay(
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    replay_arg: &ReplayArgs,
+    run_args: Vec<String>,
+) {
     // Setup
     // 1- Copy db backup path
 
-    let temp_folder = tempfile::tempdir().unwrap();
-    println!("Using temp folder: {:?}", temp_folder);
+    let mut snapshots = list_snapshots(db_path);
+    let from_slot = replay_arg
+        .from_slot
+        .map(|(period, thread)| Slot::new(period, thread));
+
+    let db_backup_path = match (&replay_arg.db_backup_path, from_slot) {
+        (Some(db_backup_path), _) => db_backup_path.clone(),
+        (None, Some(from_slot)) => match select_snapshot(&snapshots, from_slot) {
+            Some(snapshot) => {
+                println!(
+                    "Using backup {:?} (slot {}) to replay from slot {}",
+                    snapshot.path, snapshot.slot, from_slot
+                );
+                snapshot.path.clone()
+            }
+            None => {
+                eprintln!(
+                    "Cannot find any backup at or before slot {} in {:?}",
+                    from_slot, db_path
+                );
+                std::process::exit(1);
+            }
+        },
+        (None, None) => unreachable!("clap requires --backup or --from_slot"),
+    };
+
+    let mut workdir = replay_arg.workdir.as_ref().map(|workdir| {
+        Workdir::new(
+            workdir.clone(),
+            run_args,
+            replay_arg.checkpoint_every_cycles,
+        )
+    });
+
+    let temp_folder_path = match workdir.as_ref() {
+        Some(workdir) => {
+            let run_path = workdir.run_path();
+            if run_path.exists() {
+                remove_dir_all(&run_path).expect("Unable to clean workdir");
+            }
+            create_dir_all(&run_path).expect("Unable to create workdir");
+            run_path
+        }
+        None => tempfile::tempdir().unwrap().into_path(),
+    };
+    println!("Using temp folder: {:?}", temp_folder_path);
+    node_settings().print();
 
-    let temp_folder_path = temp_folder.into_path();
     let db_temp_folder_path = temp_folder_path.join("db");
     let gas_costs_temp_folder_path = temp_folder_path.join("gas_costs");
 
-    copy_dir(
-        replay_arg.db_backup_path.clone(),
-        db_temp_folder_path.clone(),
-    )
-    .expect("Unable to copy db backup path to temp dir");
+    copy_dir(db_backup_path, db_temp_folder_path.clone())
+        .expect("Unable to copy db backup path to temp dir");
 
     // 2- Copy additional files
-    let gas_costs_folder = initial_rolls_path.parent().unwrap().join("gas_costs");
+    let gas_costs_folder = replay_arg
+        .gas_costs_path
+        .clone()
+        .unwrap_or_else(|| initial_rolls_path.parent().unwrap().join("gas_costs"));
+    check_gas_costs_folder(&gas_costs_folder).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    println!("Using gas costs from: {:?}", gas_costs_folder);
     copy_dir(gas_costs_folder, gas_costs_temp_folder_path.clone())
         .expect("Unable to copy gas costs folder to temp dir");
 
@@ -121,6 +232,7 @@ fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
 
     // POS - Selector
     let selector_config = get_selector_config();
+    println!("Selector config: {:#?}", selector_config);
     let (_selector_manager, selector_controller) =
         start_selector_worker(selector_config).expect("could not start selector worker");
 
@@ -148,8 +260,27 @@ fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
 
     // launch execution module
 
-    let execution_config =
-        get_execution_config(db_snapshot_last_slot.period, &gas_costs_temp_folder_path);
+    let module_cache_path = replay_arg
+        .module_cache
+        .resolve(&temp_folder_path, workdir.as_ref().map(|w| w.path()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    println!(
+        "Module cache ({}): {:?}",
+        replay_arg.module_cache, module_cache_path
+    );
+    let execution_config = get_execution_config(
+        db_snapshot_last_slot.period,
+        &gas_costs_temp_folder_path,
+        module_cache_path,
+    );
+    println!("Execution config: {:#?}", execution_config);
+    if replay_arg.precompile_modules {
+        println!("Compiling the bytecodes of the ledger...");
+        precompile_modules(&final_state, &execution_config);
+    }
 
     let execution_channels = ExecutionChannels {
         slot_execution_output_sender: broadcast::channel(
@@ -163,20 +294,42 @@ fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
         .0,
     };
 
+    // Subscribe before the execution worker starts so no finalized slot is missed
+    let mut slot_execution_listener = SlotExecutionListener::new(
+        &execution_channels.slot_execution_output_sender,
+        Duration::from_secs(replay_arg.execution_timeout),
+    );
+
+    #[cfg(feature = "execution-trace")]
+    let trace_sink = replay_arg.traces_path.as_ref().map(|traces_path| {
+        println!("Writing execution traces to: {:?}", traces_path);
+        TraceSink::start(
+            &execution_channels,
+            &*final_state.read(),
+            traces_path.clone(),
+            from_slot,
+            TraceFilter {
+                addresses: replay_arg.trace_addresses.clone(),
+                operation_ids: replay_arg.trace_operation_ids.clone(),
+            },
+        )
+    });
+
     let node_wallet = Arc::new(RwLock::new(
         Wallet::new(
-            PathBuf::from("config/staking_wallets"), // SETTINGS.factory.staking_wallet_path
+            PathBuf::from("config/staking_wallets"),
             "1234".to_string(),
-            *CHAINID,
+            network().chain_id,
         )
         .unwrap(),
     ));
 
+    // Note: the metrics & staking wallet settings of the node are not used (replay values)
     let (massa_metrics, _metrics_stopper) = MassaMetrics::new(
-        false,                                       // SETTINGS.metrics.enabled,
-        SocketAddr::from_str("[::]:31248").unwrap(), // SETTINGS.metrics.bind,
+        false,
+        SocketAddr::from_str("[::]:31248").unwrap(),
         THREAD_COUNT,
-        MassaTime::from_millis(5000).to_duration(), // SETTINGS.metrics.tick_delay.to_duration(),
+        Duration::from_secs(5),
     );
 
     let (_execution_manager, execution_controller) = start_execution_worker(
@@ -191,30 +344,142 @@ fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
 
     println!("Execution manager & Execution controller done!");
 
-    let dumped_slots = list_dumped_blocks(&replay_arg.dump_block_path);
-    let first_slot = dumped_slots.iter().min().unwrap();
-    let last_slot = dumped_slots.iter().max().unwrap();
+    let blocks_format = replay_arg
+        .blocks_format
+        .resolve(replay_arg.dump_block_path())
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    println!("Reading dumped blocks as {:?}", blocks_format);
+    let block_db =
+        BlockStorage::open(replay_arg.dump_block_path(), blocks_format).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let (Some(first_slot), Some(last_slot)) = (block_db.first_slot(), block_db.last_slot()) else {
+        eprintln!("No dumped block in {:?}", replay_arg.dump_block_path());
+        std::process::exit(1);
+    };
     println!("first block in dumped block pool {:?}", first_slot);
     println!("last block in dumped block pool {:?}", last_slot);
+    let replay_end = replay_end(&block_db.last_periods());
+    match replay_end {
+        Some(replay_end) => println!(
+            "Dumped blocks can finalize the slots until slot {}",
+            replay_end
+        ),
+        None => println!("Dumped blocks cannot finalize any slot (a thread has no block)"),
+    }
 
-    let mut slot = db_snapshot_last_slot;
-
-    cfg_if! {
-        if #[cfg(feature = "db_storage_backend")] {
-            let block_db =
-                RocksDBStorageBackend::new(
-                replay_arg.dump_block_path.clone())
-            ;
-        } else if #[cfg(feature = "file_storage_backend")] {
-            let block_db =
-                FileStorageBackend::new(
-                replay_arg.dump_block_path.clone())
-            ;
-        } else  {
-            compile_error!("Slot replayer binary require either feature `db_storage_backend` or feature `file_storage_backend`");
+    if replay_arg.verify_blocks {
+        println!("Checking dumped blocks...");
+        let until_slot = replay_arg
+            .until_slot
+            .map(|(period, thread)| Slot::new(period, thread));
+        let failures = verify_blocks(
+            &block_db,
+            db_snapshot_last_slot.get_next_slot(THREAD_COUNT).ok(),
+            until_slot,
+        );
+        if !report_failures(&failures) {
+            eprintln!("Invalid dumped blocks, aborting replay");
+            std::process::exit(1);
         }
     }
 
+    if let Some(reference_backup) = replay_arg.reference_backup.as_ref() {
+        snapshots.push(read_snapshot(reference_backup.clone()));
+        snapshots.sort_by_key(|s| s.slot);
+    }
+    let mut snapshot_checker = SnapshotChecker::new(snapshots, db_snapshot_last_slot);
+    let snapshot_writer = SnapshotWriter::new(
+        db_path.to_path_buf(),
+        replay_arg
+            .snapshot_at
+            .iter()
+            .map(|(period, thread)| Slot::new(*period, *thread))
+            .collect(),
+        replay_arg.snapshot_every_cycle,
+    );
+    let mut slot_handler = FinalizedSlotHandler::new(
+        final_state.clone(),
+        execution_controller.clone(),
+        replay_arg,
+        from_slot,
+    );
+    if let Some(from_slot) = from_slot {
+        println!("Replaying silently until slot {}", from_slot);
+    }
+
+    let mut slot = db_snapshot_last_slot;
+    // Last slot made final (by feeding its block, or a later block of its thread if missed)
+    let mut last_final_slot: Option<Slot> = None;
+    // Last block fed to execution for each thread
+    let mut last_block_per_thread: Vec<Option<(BlockId, Address)>> =
+        vec![None; THREAD_COUNT as usize];
+    // Blocks fed before their turn, to make a missed slot of the same thread final
+    let mut pre_fed_slots: BTreeSet<Slot> = BTreeSet::new();
+
+    // Invalid dumped blocks, replayed as missed slots (with --on_bad_block skip)
+    let skipped_blocks: RefCell<BTreeSet<Slot>> = RefCell::new(BTreeSet::new());
+    let read_block = |slot: &Slot| -> Option<DumpedBlock> {
+        match block_db.read_block(slot)? {
+            Ok(dumped_block) => Some(dumped_block),
+            Err(e) => match replay_arg.on_bad_block {
+                BadBlockPolicy::Abort => {
+                    eprintln!("Invalid dumped block, {}", e);
+                    eprintln!("Use --on_bad_block skip to replay it as a missed slot");
+                    std::process::exit(1);
+                }
+                BadBlockPolicy::Skip => {
+                    if skipped_blocks.borrow_mut().insert(*slot) {
+                        println!("Skipping invalid dumped block, {}", e);
+                    }
+                    None
+                }
+            },
+        }
+    };
+
+    let feed_block =
+        |slot: Slot,
+         dumped_block: DumpedBlock,
+         last_block_per_thread: &mut Vec<Option<(BlockId, Address)>>| {
+            let mut storage = Storage::create_root();
+
+            let DumpedBlock { block, operations } = dumped_block;
+            info!("Find {} operations", operations.len());
+            storage.store_operations(operations);
+
+            trace!("add block id: {} in storage...", block.id);
+
+            let thread = slot.thread as usize;
+            let parent_id = block.content.header.content.parents.get(thread).copied();
+            let same_thread_parent_creator = same_thread_parent_creator(
+                slot,
+                parent_id,
+                last_block_per_thread[thread],
+                &read_block,
+            );
+            last_block_per_thread[thread] = Some((block.id, block.content_creator_address));
+
+            let block_id = block.id;
+            storage.store_block(block);
+
+            let finalized_blocks = HashMap::from([(slot, block_id)]);
+
+            let execution_block_metadata = ExecutionBlockMetadata {
+                same_thread_parent_creator,
+                storage: Some(storage),
+            };
+
+            let mut block_metadata = PreHashMap::default();
+            block_metadata.insert(block_id, execution_block_metadata);
+
+            execution_controller.update_blockclique_status(finalized_blocks, None, block_metadata);
+        };
+
     // let blocks = block_storer::fetch_block_from_node_storer();
 
     while let Ok(next_slot) = slot.get_next_slot(THREAD_COUNT) {
@@ -227,59 +492,112 @@ fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
             }
         }
 
-        if next_slot > *last_slot {
-            println!("Last slot reached, exiting now...");
+        if replay_end.map_or(true, |replay_end| next_slot > replay_end) {
+            if next_slot > last_slot {
+                println!("Last slot reached, exiting now...");
+            } else {
+                println!(
+                    "Slot {} cannot be finalized (no later block in thread {}), exiting now...",
+                    next_slot, next_slot.thread
+                );
+            }
             break;
         }
         trace!("Read dumped block - next_slot: {:?}", next_slot);
 
-        let slot_same_thread_parent = Slot::new(next_slot.period - 1, next_slot.thread);
-
-        let dumped_block_content_ = block_db.read(&next_slot);
-        let dumped_block_parent_ = block_db.read(&slot_same_thread_parent).unwrap(); // FIXME
-
-        match dumped_block_content_ {
-            Some(dump_block_content) => {
-                let filled_block =
-                    grpc_model::FilledBlock::decode(&dump_block_content[..]).unwrap();
-                let mut storage = Storage::create_root();
-
-                let operations = secure_shared_operations_from_filled_operation_entries(
-                    &filled_block.operations,
-                );
-                info!("Find {} operations", operations.len());
-                storage.store_operations(operations);
-
-                let block: SecureShareBlock = secure_share_block_from_filled_block(filled_block);
-                trace!("add block id: {} in storage...", block.id);
-
-                let block_id = block.id.clone();
-                storage.store_block(block);
-
-                let finalized_blocks = HashMap::from([(next_slot, block_id)]);
-
-                let filled_block_parent =
-                    grpc_model::FilledBlock::decode(&dumped_block_parent_[..]).unwrap();
-
-                let execution_block_metadata = ExecutionBlockMetadata {
-                    same_thread_parent_creator: Some(address_from_str(
-                        &filled_block_parent.header.unwrap().content_creator_address,
-                    )),
-                    storage: Some(storage),
-                };
-
-                let mut block_metadata = PreHashMap::default();
-                block_metadata.insert(block_id, execution_block_metadata);
-
-                execution_controller.update_blockclique_status(
-                    finalized_blocks,
-                    None,
-                    block_metadata,
-                );
+        let already_final = pre_fed_slots
+            .iter()
+            .any(|s| s.thread == next_slot.thread && s.period >= next_slot.period);
+
+        if !already_final {
+            match read_block(&next_slot) {
+                Some(dumped_block) => {
+                    slot_handler.on_block(next_slot, &dumped_block);
+                    #[cfg(feature = "execution-trace")]
+                    if let Some(trace_sink) = trace_sink.as_ref() {
+                        trace_sink.on_block(next_slot, &dumped_block);
+                    }
+                    feed_block(next_slot, dumped_block, &mut last_block_per_thread);
+                }
+                None => {
+                    // A missed slot is final once a later block of the same thread is final
+                    let next_block_in_thread = (next_slot.period + 1..=last_slot.period)
+                        .map(|period| Slot::new(period, next_slot.thread))
+                        .find_map(|s| read_block(&s).map(|dumped_block| (s, dumped_block)));
+
+                    match next_block_in_thread {
+                        Some((block_slot, dumped_block)) => {
+                            info!(
+                                "Missed slot: {}, feeding block of slot {} in advance",
+                                next_slot, block_slot
+                            );
+                            slot_handler.on_block(block_slot, &dumped_block);
+                            #[cfg(feature = "execution-trace")]
+                            if let Some(trace_sink) = trace_sink.as_ref() {
+                                trace_sink.on_block(block_slot, &dumped_block);
+                            }
+                            feed_block(block_slot, dumped_block, &mut last_block_per_thread);
+                            // the missed slots of the thread up to the block can be finalized now
+                            for period in next_slot.period..=block_slot.period {
+                                slot_handler.on_fed(Slot::new(period, next_slot.thread));
+                            }
+                            pre_fed_slots.insert(block_slot);
+                        }
+                        None => {
+                            // only with --on_bad_block skip (see replay_end otherwise)
+                            warn!(
+                                "Missed slot: {} and no later valid dumped block in its thread",
+                                next_slot
+                            );
+                            println!(
+                                "Slot {} cannot be finalized (no later valid block in thread {}), exiting now...",
+                                next_slot, next_slot.thread
+                            );
+                            break;
+                        }
+                    }
+                }
             }
-            None => {
-                warn!("Unable to read dumped block for slot: {}", next_slot);
-                break;
+        }
+        slot_handler.on_fed(next_slot);
+        pre_fed_slots.retain(|s| *s > next_slot);
+        last_final_slot = Some(next_slot);
+
+        let check_snapshot = snapshot_checker
+            .next_slot()
+            .map_or(false, |snapshot_slot| snapshot_slot <= next_slot);
+        let write_snapshot = snapshot_writer.wants(next_slot);
+        let write_checkpoint = workdir.as_ref().map_or(false, |w| w.wants(next_slot));
+        if check_snapshot
+            || write_snapshot
+            || write_checkpoint
+            || slot_handler.step_by_step(next_slot)
+        {
+            wait_for_slot(&mut slot_execution_listener, &mut slot_handler, next_slot);
+            if let Some(divergent_slot) = slot_handler.divergent_slot() {
+                print_dumped_block(divergent_slot, block_db.read_block(&divergent_slot));
+                std::process::exit(EXIT_DIVERGENCE);
+            }
+        }
+        if check_snapshot {
+            let hash = final_state.read().get_database().read().get_xof_db_hash();
+            snapshot_checker.check(next_slot, &hash);
+        }
+        if write_snapshot || write_checkpoint {
+            let final_state = final_state.read();
+            let db = final_state.get_database().read();
+            if db.get_change_id().ok() != Some(next_slot) {
+                warn!(
+                    "Final state already past slot {}, backup not written",
+                    next_slot
+                );
+            } else {
+                if write_snapshot {
+                    snapshot_writer.write(&**db, next_slot);
+                }
+                if let Some(workdir) = workdir.as_mut().filter(|_| write_checkpoint) {
+                    workdir.checkpoint(&**db, next_slot, replay_arg);
+                }
             }
         }
 
@@ -288,103 +606,152 @@ fn replay(initial_rolls_path: &Path, replay_arg: &ReplayArgs) {
 
     trace!("End of while loop...");
 
-    let d = Duration::from_secs(
-        (last_slot.period - db_snapshot_last_slot.period + 1) * EXECUTION_TIME_PER_BLOCK_IN_S,
-    );
+    let Some(last_final_slot) = last_final_slot else {
+        println!("No block fed to execution, nothing to wait for");
+        return;
+    };
+
     println!(
-        "Waiting {} seconds for the slot execution to complete
-    (depending on your computeur you may need to adjust this value)",
-        d.as_secs()
+        "Waiting for slot {} to be finalized (timeout: {}s without progress)...",
+        last_final_slot, replay_arg.execution_timeout
     );
-    thread::sleep(d);
-}
+    wait_for_slot(
+        &mut slot_execution_listener,
+        &mut slot_handler,
+        last_final_slot,
+    );
+    println!("Slot {} finalized, replay done!", last_final_slot);
+    if !skipped_blocks.borrow().is_empty() {
+        println!(
+            "{} invalid dumped blocks skipped (replayed as missed slots): {:?}",
+            skipped_blocks.borrow().len(),
+            skipped_blocks.borrow()
+        );
+    }
+    if slot_handler.executed_denunciations() > 0 {
+        println!(
+            "{} denunciations executed during the replay (slashing applied)",
+            slot_handler.executed_denunciations()
+        );
+    }
 
-#[cfg(feature = "db_storage_backend")]
-fn list_dumped_blocks_db(path: &PathBuf) -> Vec<Slot> {
-    let opts = rocksdb::Options::default();
-
-    let blocks_db = DB::open_for_read_only(&opts, path, true)
-        .unwrap_or_else(|_| panic!("failed to open db at {:?}", path));
-    blocks_db
-        .iterator(IteratorMode::Start)
-        .map(|x| match x {
-            Ok((k, _v)) => {
-                let buffer: &[u8; SLOT_KEY_SIZE] = &k.to_vec().try_into().unwrap();
-                Slot::from_bytes_key(buffer)
-            }
-            Err(e) => panic!("{}", e),
-        })
-        .collect()
-}
+    if let Some(perf_report_path) = replay_arg.perf_report_path.as_ref() {
+        slot_handler.write_perf_report(perf_report_path);
+    }
 
-#[cfg(feature = "file_storage_backend")]
-fn list_dumped_blocks_file(path: &PathBuf) -> Vec<Slot> {
-    use std::fs::read_dir;
-    let dir = read_dir(path).unwrap();
-    let slots: Vec<_> = dir
-        .map(|e| {
-            let file_name = &e.unwrap().file_name();
-            let file_name = file_name.to_str().unwrap();
-            let slot: Vec<_> = file_name.split('_').collect();
-            let thread = slot.get(2).unwrap().parse::<u8>().unwrap();
-            let period = slot
-                .get(3)
-                .unwrap()
-                .split('.')
-                .nth(0)
-                .unwrap()
-                .parse::<u64>()
-                .unwrap();
-            Slot::new(period, thread)
-        })
-        .collect();
-
-    slots
+    #[cfg(feature = "execution-trace")]
+    if let Some(trace_sink) = trace_sink.as_ref() {
+        trace_sink.wait_for(last_final_slot, TRACE_SINK_GRACE_PERIOD);
+    }
+
+    if let Some(divergent_slot) = slot_handler.divergent_slot() {
+        print_dumped_block(divergent_slot, block_db.read_block(&divergent_slot));
+        std::process::exit(EXIT_DIVERGENCE);
+    }
+
+    if !snapshot_checker.report() {
+        eprintln!("Replayed final state diverged from backup(s)");
+        std::process::exit(EXIT_DIVERGENCE);
+    }
 }
 
-fn list_dumped_blocks(path: &PathBuf) -> Vec<Slot> {
-    cfg_if! {
-        if #[cfg(feature = "db_storage_backend")] {
-            list_dumped_blocks_db(path)
+/// Creator of the same thread parent of a block
+///
+/// Use the last block fed in this thread, or look for the parent in the dumped blocks
+fn same_thread_parent_creator(
+    slot: Slot,
+    parent_id: Option<BlockId>,
+    last_block_in_thread: Option<(BlockId, Address)>,
+    read_block: &impl Fn(&Slot) -> Option<DumpedBlock>,
+) -> Option<Address> {
+    if let Some((block_id, creator)) = last_block_in_thread {
+        if parent_id.map_or(true, |parent_id| parent_id == block_id) {
+            return Some(creator);
         }
-        else if #[cfg(feature = "file_storage_backend")] {
-            list_dumped_blocks_file(path)
-        } else  {
-            compile_error!("Requise either feature db_storage_backend or feature  file_storage_backend");
+    }
+
+    let first_period = slot.period.saturating_sub(MAX_PARENT_LOOKBACK_PERIODS);
+    for period in (first_period..slot.period).rev() {
+        let Some(DumpedBlock { block, .. }) = read_block(&Slot::new(period, slot.thread)) else {
+            continue;
+        };
+        if parent_id.map_or(true, |parent_id| parent_id == block.id) {
+            return Some(block.content_creator_address);
         }
     }
-}
 
-fn list_snapshot(db_path: &Path) {
-    let pattern_ = db_path.join("backup_*_*");
-    let pattern = pattern_.to_str().unwrap();
+    match last_block_in_thread {
+        Some((block_id, creator)) => {
+            warn!(
+                "Slot {}: same thread parent {:?} not found, using creator of block {}",
+                slot, parent_id, block_id
+            );
+            Some(creator)
+        }
+        None => {
+            warn!(
+                "Slot {}: same thread parent {:?} not found in dumped blocks",
+                slot, parent_id
+            );
+            None
+        }
+    }
+}
 
-    let glob_res = glob(pattern).expect("Failed to read glob pattern");
-    let mut found = 0;
+/// Print the block (and its operations) dumped for the given slot
+fn print_dumped_block(slot: Slot, dumped_block: Option<Result<DumpedBlock, ConversionError>>) {
+    let dumped_block = match dumped_block {
+        Some(Ok(dumped_block)) => dumped_block,
+        Some(Err(e)) => {
+            println!("Invalid dumped block, {}", e);
+            return;
+        }
+        None => {
+            println!("No dumped block for slot {} (missed slot)", slot);
+            return;
+        }
+    };
+    let DumpedBlock { block, operations } = dumped_block;
 
-    for entry in glob_res {
-        match entry {
-            Ok(path) => {
-                let db_config_from_backup = get_db_config(path.clone());
-                let wrapped_db = WrappedMassaDB::new(db_config_from_backup, false);
-                let db_slot = wrapped_db.0.get_change_id().unwrap();
+    println!(
+        "Block {} at slot {}: {:#?}",
+        block.id, slot, block.content.header.content
+    );
+    for operation in operations {
+        println!("Operation {}: {:#?}", operation.id, operation.content);
+    }
+}
 
-                println!(
-                    "Backup (path: {:?}): hash: {}, last slot: {}",
-                    path.display(),
-                    wrapped_db.0.get_xof_db_hash(),
-                    db_slot
-                );
+/// Wait for the given slot to be finalized, exit if execution stalled
+fn wait_for_slot(
+    listener: &mut SlotExecutionListener,
+    slot_handler: &mut FinalizedSlotHandler,
+    slot: Slot,
+) {
+    if let Err(e) = listener.wait_for(slot, |output, received_at| {
+        slot_handler.on_finalized(output, received_at)
+    }) {
+        eprintln!("Replay failed: {}", e);
+        std::process::exit(1);
+    }
+}
 
-                found += 1;
-            }
-            Err(e) => {
-                println!("Error: {:?}", e);
-            }
-        }
+fn list_snapshot(db_path: &Path) {
+    let snapshots = list_snapshots(db_path);
+
+    for snapshot in snapshots.iter() {
+        println!(
+            "Backup (path: {:?}): hash: {}, last slot: {}",
+            snapshot.path.display(),
+            snapshot.hash,
+            snapshot.slot
+        );
     }
 
-    if found == 0 {
-        println!("Cannot find any backup with pattern: {:?}", pattern);
+    if snapshots.is_empty() {
+        println!(
+            "Cannot find any backup with pattern: {:?}",
+            snapshot_pattern(db_path)
+        );
     }
 }
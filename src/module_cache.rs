@@ -0,0 +1,135 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use massa_db_exports::{LEDGER_PREFIX, STATE_CF};
+use massa_execution_exports::ExecutionConfig;
+use massa_final_state::FinalStateController;
+use massa_ledger_exports::{KeyDeserializer, KeyType};
+use massa_models::config::{MAX_DATASTORE_KEY_LENGTH, MAX_GAS_PER_BLOCK};
+use massa_module_cache::config::ModuleCacheConfig;
+use massa_module_cache::controller::ModuleCache;
+use massa_serialization::{DeserializeError, Deserializer};
+use parking_lot::RwLock;
+
+/// Name of the module cache folder in the replay workspace
+const MODULE_CACHE_FOLDER: &str = "module_cache";
+
+/// Where the compiled modules (execution hd cache) are stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleCacheMode {
+    /// Empty cache in the replay workspace
+    Cold,
+    /// Cache of the workdir, kept from one replay to the next (requires --workdir)
+    Warm,
+    /// Cache in the given folder (kept, must not be used by two replays at the same time)
+    Path(PathBuf),
+}
+
+impl FromStr for ModuleCacheMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cold" => Ok(ModuleCacheMode::Cold),
+            "warm" => Ok(ModuleCacheMode::Warm),
+            "" => Err("Module cache must be cold, warm or a folder".to_string()),
+            path => Ok(ModuleCacheMode::Path(PathBuf::from(path))),
+        }
+    }
+}
+
+impl fmt::Display for ModuleCacheMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleCacheMode::Cold => write!(f, "cold"),
+            ModuleCacheMode::Warm => write!(f, "warm"),
+            ModuleCacheMode::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl ModuleCacheMode {
+    /// Folder of the module cache
+    ///
+    /// `run_path` is the replay workspace (cleaned at each run), `workdir` the folder given
+    /// with --workdir (if any)
+    pub fn resolve(&self, run_path: &Path, workdir: Option<&Path>) -> Result<PathBuf, String> {
+        let path = match self {
+            ModuleCacheMode::Cold => {
+                let path = run_path.join(MODULE_CACHE_FOLDER);
+                if path.exists() {
+                    std::fs::remove_dir_all(&path)
+                        .map_err(|e| format!("Cannot clean module cache {:?}: {}", path, e))?;
+                }
+                path
+            }
+            ModuleCacheMode::Warm => workdir
+                .ok_or_else(|| {
+                    "--module_cache warm requires --workdir (or a module cache folder)".to_string()
+                })?
+                .join(MODULE_CACHE_FOLDER),
+            ModuleCacheMode::Path(path) => path.clone(),
+        };
+        std::fs::create_dir_all(&path)
+            .map_err(|e| format!("Cannot create module cache {:?}: {}", path, e))?;
+        Ok(path)
+    }
+}
+
+/// Compile the bytecode of every address of the ledger into the module cache
+///
+/// Note: must be called before starting the execution worker (the hd cache cannot be opened twice)
+pub fn precompile_modules(
+    final_state: &Arc<RwLock<dyn FinalStateController>>,
+    execution_config: &ExecutionConfig,
+) {
+    let started_at = Instant::now();
+    let mut module_cache = ModuleCache::new(ModuleCacheConfig {
+        hd_cache_path: execution_config.hd_cache_path.clone(),
+        gas_costs: execution_config.gas_costs.clone(),
+        lru_cache_size: execution_config.lru_cache_size,
+        hd_cache_size: execution_config.hd_cache_size,
+        snip_amount: execution_config.snip_amount,
+        max_module_length: execution_config.max_bytecode_size,
+    });
+
+    let final_state = final_state.read();
+    let key_deserializer = KeyDeserializer::new(MAX_DATASTORE_KEY_LENGTH);
+    let addresses: Vec<_> = final_state
+        .get_database()
+        .read()
+        .prefix_iterator_cf(STATE_CF, LEDGER_PREFIX.as_bytes())
+        .filter_map(|(key, _)| {
+            let (_, key) = key_deserializer
+                .deserialize::<DeserializeError>(&key[LEDGER_PREFIX.len()..])
+                .ok()?;
+            matches!(key.key_type, KeyType::BYTECODE).then_some(key.address)
+        })
+        .collect();
+
+    let (mut compiled, mut failed) = (0, 0);
+    for address in addresses.iter() {
+        let Some(bytecode) = final_state.get_ledger().get_bytecode(address) else {
+            continue;
+        };
+        if bytecode.0.is_empty() {
+            continue;
+        }
+        match module_cache.load_module(&bytecode.0, MAX_GAS_PER_BLOCK) {
+            Ok(_) => compiled += 1,
+            Err(e) => {
+                failed += 1;
+                println!("Cannot compile bytecode of {}: {}", address, e);
+            }
+        }
+    }
+    println!(
+        "{} modules compiled ({} failed) in {:.1} s",
+        compiled,
+        failed,
+        started_at.elapsed().as_secs_f64()
+    );
+}
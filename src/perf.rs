@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use massa_execution_exports::ExecutionOutput;
+use massa_final_state::FinalStateController;
+use massa_models::slot::Slot;
+use serde::{Deserialize, Serialize};
+
+use crate::async_message_tracker::AsyncMessageTracker;
+use crate::grpc_conv::DumpedBlock;
+
+/// Number of slowest slots printed (and written in the report)
+const SLOWEST_SLOTS: usize = 10;
+
+/// Block fed to the execution
+struct FedBlock {
+    block_id: String,
+    operations: usize,
+}
+
+/// Record, for every slot, the time between the moment it is fed to the execution
+/// (its block, or a later block of its thread if missed) and the moment its finalized output is received
+pub struct PerfRecorder {
+    started_at: Instant,
+    fed_at: BTreeMap<Slot, Instant>,
+    blocks: BTreeMap<Slot, FedBlock>,
+    slots: Vec<SlotPerf>,
+    async_messages: AsyncMessageTracker,
+}
+
+impl PerfRecorder {
+    /// Note: must be created before the execution finalizes any slot (see AsyncMessageTracker)
+    pub fn new(final_state: &dyn FinalStateController) -> Self {
+        Self {
+            started_at: Instant::now(),
+            fed_at: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            slots: Vec::new(),
+            async_messages: AsyncMessageTracker::new(final_state),
+        }
+    }
+
+    /// Block of the slot, fed to the execution
+    pub fn on_block(&mut self, slot: Slot, dumped_block: &DumpedBlock) {
+        self.blocks.insert(
+            slot,
+            FedBlock {
+                block_id: dumped_block.block.id.to_string(),
+                operations: dumped_block.operations.len(),
+            },
+        );
+    }
+
+    /// The slot can be finalized by the execution from now on (first call kept)
+    pub fn on_fed(&mut self, slot: Slot) {
+        self.fed_at.entry(slot).or_insert_with(Instant::now);
+    }
+
+    /// Gas used by the smart contract operations of the slot, with --measure_gas
+    pub fn on_finalized(
+        &mut self,
+        output: &ExecutionOutput,
+        gas_used: Option<&BTreeMap<String, Option<u64>>>,
+        received_at: Instant,
+        recording: bool,
+    ) {
+        // every slot, to follow the async pool
+        let async_messages_executed = self.async_messages.on_finalized(output).len();
+        let fed_at = self.fed_at.remove(&output.slot);
+        let block = self.blocks.remove(&output.slot);
+        let Some(fed_at) = fed_at.filter(|_| recording) else {
+            return;
+        };
+        self.slots.push(SlotPerf {
+            slot: output.slot.to_string(),
+            block_id: block.as_ref().map(|block| block.block_id.clone()),
+            latency_ms: millis(received_at.saturating_duration_since(fed_at)),
+            operations: block.as_ref().map_or(0, |block| block.operations),
+            executed_operations: output.state_changes.executed_ops_changes.len(),
+            gas_used: gas_used.map(|gas_used| gas_used.values().flatten().sum()),
+            async_messages_executed,
+        });
+    }
+
+    pub fn report(&self, final_state_hash: String) -> PerfReport {
+        PerfReport::new(
+            self.started_at.elapsed().as_millis() as u64,
+            self.slots.clone(),
+            peak_rss_kb(),
+            final_state_hash,
+        )
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Execution of a slot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotPerf {
+    pub slot: String,
+    /// None for a missed slot
+    pub block_id: Option<String>,
+    /// From the moment the slot is fed to the moment its finalized output is received
+    pub latency_ms: f64,
+    /// Operations in the block
+    pub operations: usize,
+    /// Operations executed (in the state changes)
+    pub executed_operations: usize,
+    /// Gas used by the smart contract operations of the block (measured ones), None without
+    /// --measure_gas
+    pub gas_used: Option<u64>,
+    /// Async messages executed (expired ones not counted)
+    pub async_messages_executed: usize,
+}
+
+/// Slot latency percentiles (millis)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyStats {
+    fn new(sorted: &[Duration]) -> Self {
+        Self {
+            p50_ms: millis(percentile(sorted, 50.0)),
+            p95_ms: millis(percentile(sorted, 95.0)),
+            p99_ms: millis(percentile(sorted, 99.0)),
+            max_ms: millis(sorted.last().copied().unwrap_or_default()),
+        }
+    }
+}
+
+/// Nearest rank percentile of sorted values (zero if empty)
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn describe_gas(gas_used: Option<u64>) -> String {
+    gas_used.map_or("unmeasured".to_string(), |gas_used| gas_used.to_string())
+}
+
+/// Peak resident set size of the process (Linux only)
+fn peak_rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// Performance of a replay, written with `replay --perf_report`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PerfReport {
+    /// From the start of the replay loop to the last finalized slot
+    pub replay_time_ms: u64,
+    /// Number of slots recorded (from --from_slot)
+    pub slot_count: usize,
+    pub block_count: usize,
+    pub operations: usize,
+    pub executed_operations: usize,
+    /// None without --measure_gas
+    pub gas_used: Option<u64>,
+    pub async_messages_executed: usize,
+    pub latency: LatencyStats,
+    pub peak_rss_kb: Option<u64>,
+    /// Hash of the final state at the end of the replay
+    pub final_state_hash: String,
+    pub slowest_slots: Vec<SlotPerf>,
+    pub slots: Vec<SlotPerf>,
+}
+
+impl PerfReport {
+    fn new(
+        replay_time_ms: u64,
+        slots: Vec<SlotPerf>,
+        peak_rss_kb: Option<u64>,
+        final_state_hash: String,
+    ) -> Self {
+        let mut latencies: Vec<Duration> = slots
+            .iter()
+            .map(|slot| Duration::from_secs_f64(slot.latency_ms / 1000.0))
+            .collect();
+        latencies.sort();
+
+        let mut slowest_slots = slots.clone();
+        slowest_slots.sort_by(|a, b| b.latency_ms.total_cmp(&a.latency_ms));
+        slowest_slots.truncate(SLOWEST_SLOTS);
+
+        Self {
+            replay_time_ms,
+            slot_count: slots.len(),
+            block_count: slots.iter().filter(|s| s.block_id.is_some()).count(),
+            operations: slots.iter().map(|s| s.operations).sum(),
+            executed_operations: slots.iter().map(|s| s.executed_operations).sum(),
+            gas_used: slots.iter().filter_map(|s| s.gas_used).reduce(|a, b| a + b),
+            async_messages_executed: slots.iter().map(|s| s.async_messages_executed).sum(),
+            latency: LatencyStats::new(&latencies),
+            peak_rss_kb,
+            final_state_hash,
+            slowest_slots,
+            slots,
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} slots ({} blocks) in {:.1} s: {} operations ({} executed), {} gas used, {} async messages executed",
+            self.slot_count,
+            self.block_count,
+            self.replay_time_ms as f64 / 1000.0,
+            self.operations,
+            self.executed_operations,
+            describe_gas(self.gas_used),
+            self.async_messages_executed
+        );
+        println!(
+            "Slot latency: p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+            self.latency.p50_ms, self.latency.p95_ms, self.latency.p99_ms, self.latency.max_ms
+        );
+        if let Some(peak_rss_kb) = self.peak_rss_kb {
+            println!("Peak RSS: {} MB", peak_rss_kb / 1024);
+        }
+        println!("Slowest slots:");
+        for slot in self.slowest_slots.iter() {
+            println!(
+                "  slot {:<10} {:>9.1} ms  {:>4} ops {:>12} gas used {:>4} async messages executed  block {}",
+                slot.slot,
+                slot.latency_ms,
+                slot.operations,
+                describe_gas(slot.gas_used),
+                slot.async_messages_executed,
+                slot.block_id.as_deref().unwrap_or("none (missed slot)")
+            );
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("Cannot create {:?}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|e| format!("Cannot write {:?}: {}", path, e))?;
+        writer
+            .flush()
+            .map_err(|e| format!("Cannot write {:?}: {}", path, e))
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Cannot open {:?}: {}", path, e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("Invalid perf report {:?}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn slot_perf(slot: &str, latency_ms: f64, gas_used: Option<u64>) -> SlotPerf {
+        SlotPerf {
+            slot: slot.to_string(),
+            block_id: gas_used.map(|_| format!("B{}", slot)),
+            latency_ms,
+            operations: 2,
+            executed_operations: 1,
+            gas_used,
+            async_messages_executed: 3,
+        }
+    }
+
+    #[test]
+    fn percentile_of_empty_values() {
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+        assert_eq!(percentile(&[], 99.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn percentile_of_a_single_value() {
+        for p in [0.0, 1.0, 50.0, 99.0, 100.0] {
+            assert_eq!(percentile(&[ms(7)], p), ms(7));
+        }
+    }
+
+    #[test]
+    fn percentile_of_even_length_values() {
+        let sorted = [ms(1), ms(2), ms(3), ms(4)];
+        // nearest rank: ceil(p * n), no interpolation
+        assert_eq!(percentile(&sorted, 0.0), ms(1));
+        assert_eq!(percentile(&sorted, 25.0), ms(1));
+        assert_eq!(percentile(&sorted, 50.0), ms(2));
+        assert_eq!(percentile(&sorted, 51.0), ms(3));
+        assert_eq!(percentile(&sorted, 75.0), ms(3));
+        assert_eq!(percentile(&sorted, 95.0), ms(4));
+        assert_eq!(percentile(&sorted, 100.0), ms(4));
+    }
+
+    #[test]
+    fn latency_stats() {
+        let sorted: Vec<Duration> = (1..=100).map(ms).collect();
+        let stats = LatencyStats::new(&sorted);
+        assert_eq!(stats.p50_ms, 50.0);
+        assert_eq!(stats.p95_ms, 95.0);
+        assert_eq!(stats.p99_ms, 99.0);
+        assert_eq!(stats.max_ms, 100.0);
+
+        let stats = LatencyStats::new(&[]);
+        assert_eq!(stats.p50_ms, 0.0);
+        assert_eq!(stats.max_ms, 0.0);
+    }
+
+    #[test]
+    fn report_aggregates() {
+        let slots = vec![
+            slot_perf("1.0", 30.0, Some(1000)),
+            slot_perf("1.1", 10.0, None),
+            slot_perf("1.2", 20.0, Some(500)),
+        ];
+        let report = PerfReport::new(1234, slots, Some(2048), "hash".to_string());
+        assert_eq!(report.replay_time_ms, 1234);
+        assert_eq!(report.slot_count, 3);
+        assert_eq!(report.block_count, 2);
+        assert_eq!(report.operations, 6);
+        assert_eq!(report.executed_operations, 3);
+        assert_eq!(report.gas_used, Some(1500));
+        assert_eq!(report.async_messages_executed, 9);
+        assert_eq!(report.latency.p50_ms, 20.0);
+        assert_eq!(report.latency.max_ms, 30.0);
+        let slowest: Vec<&str> = report
+            .slowest_slots
+            .iter()
+            .map(|s| s.slot.as_str())
+            .collect();
+        assert_eq!(slowest, ["1.0", "1.2", "1.1"]);
+        assert_eq!(report.slots.len(), 3);
+    }
+
+    #[test]
+    fn report_without_measured_gas() {
+        let report = PerfReport::new(0, vec![slot_perf("1.0", 5.0, None)], None, String::new());
+        assert_eq!(report.gas_used, None);
+
+        let report = PerfReport::new(0, Vec::new(), None, String::new());
+        assert_eq!(report.slot_count, 0);
+        assert_eq!(report.latency.max_ms, 0.0);
+        assert!(report.slowest_slots.is_empty());
+    }
+
+    #[test]
+    fn slowest_slots_truncated() {
+        let slots = (0..SLOWEST_SLOTS + 5)
+            .map(|i| slot_perf(&format!("{}.0", i), i as f64, Some(1)))
+            .collect();
+        let report = PerfReport::new(0, slots, None, String::new());
+        assert_eq!(report.slowest_slots.len(), SLOWEST_SLOTS);
+        assert_eq!(
+            report.slowest_slots[0].latency_ms,
+            (SLOWEST_SLOTS + 4) as f64
+        );
+    }
+
+    #[test]
+    fn report_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("perf.json");
+        let report = PerfReport::new(
+            10,
+            vec![slot_perf("1.0", 5.0, Some(42))],
+            Some(1),
+            "hash".to_string(),
+        );
+        report.write(&path).unwrap();
+        let read = PerfReport::read(&path).unwrap();
+        assert_eq!(read.gas_used, Some(42));
+        assert_eq!(read.slots[0].async_messages_executed, 3);
+        assert_eq!(read.final_state_hash, "hash");
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs::{remove_dir_all, rename, File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use massa_db_exports::MassaDBController;
+use massa_models::config::{PERIODS_PER_CYCLE, THREAD_COUNT};
+use massa_models::slot::Slot;
+use serde::{Deserialize, Serialize};
+
+use crate::args::{Cli, Commands, ReplayArgs};
+use crate::config::init_config;
+use crate::replay;
+use crate::snapshot::{backup_path, write_backup};
+#[cfg(feature = "execution-trace")]
+use crate::trace_sink::remove_traces_after;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// State of a replay run, written in its workdir at every checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunManifest {
+    /// Command line of the run
+    pub args: Vec<String>,
+    /// Directory the run was started from: relative paths of the command line are resolved from it
+    pub cwd: PathBuf,
+    /// Slot of the checkpoint (period, thread)
+    pub last_finalized_slot: (u64, u8),
+    /// Db backup at the end of last_finalized_slot
+    pub checkpoint: PathBuf,
+    /// Size of the --output file at the checkpoint
+    pub output_offset: Option<u64>,
+    /// Size of the --digest file at the checkpoint
+    pub digest_offset: Option<u64>,
+}
+
+impl RunManifest {
+    pub fn load(workdir: &Path) -> Result<Self, String> {
+        let path = workdir.join(MANIFEST_FILE_NAME);
+        let file = File::open(&path).map_err(|e| format!("cannot open {:?}: {}", path, e))?;
+        serde_json::from_reader(file).map_err(|e| format!("cannot parse {:?}: {}", path, e))
+    }
+
+    pub fn save(&self, workdir: &Path) {
+        // write then rename so a crash never leaves a partial manifest
+        let tmp_path = workdir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        let file = File::create(&tmp_path).expect("Failed to create run manifest");
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .expect("Failed to write run manifest");
+        rename(&tmp_path, workdir.join(MANIFEST_FILE_NAME)).expect("Failed to write run manifest");
+    }
+}
+
+fn file_len(path: Option<&PathBuf>) -> Option<u64> {
+    path.map(|path| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0))
+}
+
+/// Drop what was written to the file after the checkpoint
+fn truncate(path: Option<&PathBuf>, offset: Option<u64>) {
+    if let (Some(path), Some(offset)) = (path, offset) {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Failed to open {:?}: {}", path, e));
+        if file.metadata().map(|m| m.len() > offset).unwrap_or(false) {
+            println!("Truncating {:?} to {} bytes", path, offset);
+            file.set_len(offset)
+                .unwrap_or_else(|e| panic!("Failed to truncate {:?}: {}", path, e));
+        }
+    }
+}
+
+/// Periodic checkpoints of a replay (db backup + run manifest) in its workdir
+pub struct Workdir {
+    path: PathBuf,
+    args: Vec<String>,
+    cwd: PathBuf,
+    checkpoint_every_cycles: u64,
+    last_checkpoint: Option<PathBuf>,
+}
+
+impl Workdir {
+    pub fn new(path: PathBuf, args: Vec<String>, checkpoint_every_cycles: u64) -> Self {
+        let last_checkpoint = RunManifest::load(&path).ok().map(|m| m.checkpoint);
+        Self {
+            path,
+            args,
+            cwd: std::env::current_dir().expect("Cannot read the current directory"),
+            checkpoint_every_cycles: checkpoint_every_cycles.max(1),
+            last_checkpoint,
+        }
+    }
+
+    /// Folder where the replay db (and other temporary files) are stored
+    ///
+    /// Note: on the same filesystem as the checkpoints, so db backups are cheap
+    pub fn run_path(&self) -> PathBuf {
+        self.path.join("run")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True if a checkpoint must be written at the end of the given slot
+    pub fn wants(&self, slot: Slot) -> bool {
+        let cycle = slot.period / PERIODS_PER_CYCLE;
+        slot.period % PERIODS_PER_CYCLE == PERIODS_PER_CYCLE - 1
+            && slot.thread == THREAD_COUNT - 1
+            && cycle % self.checkpoint_every_cycles == 0
+    }
+
+    /// Write a checkpoint (db must be at the end of `slot`), then remove the previous one
+    pub fn checkpoint(&mut self, db: &dyn MassaDBController, slot: Slot, replay_arg: &ReplayArgs) {
+        let checkpoint = backup_path(&self.path, slot);
+        if checkpoint.exists() {
+            remove_dir_all(&checkpoint).expect("Unable to remove previous checkpoint");
+        }
+        write_backup(db, slot, &checkpoint);
+
+        RunManifest {
+            args: self.args.clone(),
+            cwd: self.cwd.clone(),
+            last_finalized_slot: (slot.period, slot.thread),
+            checkpoint: checkpoint.clone(),
+            output_offset: file_len(replay_arg.output_path.as_ref()),
+            digest_offset: file_len(replay_arg.digest_path.as_ref()),
+        }
+        .save(&self.path);
+        println!("Checkpoint written at slot {}: {:?}", slot, checkpoint);
+
+        if let Some(previous) = self.last_checkpoint.replace(checkpoint.clone()) {
+            if previous != checkpoint && previous.exists() {
+                remove_dir_all(&previous).expect("Unable to remove previous checkpoint");
+            }
+        }
+    }
+}
+
+/// Resume the replay from the last checkpoint written in workdir
+pub fn resume(workdir: &Path) {
+    let manifest = RunManifest::load(workdir).unwrap_or_else(|e| {
+        eprintln!("Cannot resume: {}", e);
+        std::process::exit(1);
+    });
+    let workdir = workdir
+        .canonicalize()
+        .unwrap_or_else(|e| panic!("Cannot resolve {:?}: {}", workdir, e));
+    std::env::set_current_dir(&manifest.cwd).unwrap_or_else(|e| {
+        eprintln!(
+            "Cannot resume, run directory {:?} not available: {}",
+            manifest.cwd, e
+        );
+        std::process::exit(1);
+    });
+    let cli = Cli::try_parse_from(&manifest.args).unwrap_or_else(|e| {
+        eprintln!("Cannot resume, invalid command line in manifest: {}", e);
+        std::process::exit(1);
+    });
+    let Commands::Replay(mut replay_arg) = cli.command.clone() else {
+        eprintln!("Cannot resume, manifest is not a replay run");
+        std::process::exit(1);
+    };
+    if replay_arg.perf_report_path.is_some() {
+        eprintln!("Cannot resume a replay with --perf_report: the report would miss the slots before the checkpoint, replay from the start");
+        std::process::exit(1);
+    }
+    init_config(&cli);
+
+    let (period, thread) = manifest.last_finalized_slot;
+    let checkpoint_slot = Slot::new(period, thread);
+    println!(
+        "Resuming replay from checkpoint {:?} (slot {})",
+        manifest.checkpoint, checkpoint_slot
+    );
+    truncate(replay_arg.output_path.as_ref(), manifest.output_offset);
+    truncate(replay_arg.digest_path.as_ref(), manifest.digest_offset);
+    #[cfg(feature = "execution-trace")]
+    if let Some(traces_path) = replay_arg.traces_path.as_ref() {
+        let removed = remove_traces_after(traces_path, checkpoint_slot)
+            .unwrap_or_else(|e| panic!("Failed to clean trace folder {:?}: {}", traces_path, e));
+        println!(
+            "Removed {} trace files written after the checkpoint from {:?}",
+            removed, traces_path
+        );
+    }
+
+    replay_arg.db_backup_path = Some(manifest.checkpoint.clone());
+    replay_arg.workdir = Some(workdir);
+    replay(
+        cli.db_path(),
+        cli.initial_rolls_path(),
+        &replay_arg,
+        manifest.args.clone(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(dir: &Path) -> RunManifest {
+        RunManifest {
+            args: vec![
+                "massa-slot-replayer".to_string(),
+                "--path".to_string(),
+                "backups".to_string(),
+                "replay".to_string(),
+            ],
+            cwd: dir.to_path_buf(),
+            last_finalized_slot: (127, 31),
+            checkpoint: dir.join("backup_127_31"),
+            output_offset: Some(42),
+            digest_offset: None,
+        }
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let saved = manifest(dir.path());
+        saved.save(dir.path());
+
+        let loaded = RunManifest::load(dir.path()).unwrap();
+        assert_eq!(loaded.args, saved.args);
+        assert_eq!(loaded.cwd, saved.cwd);
+        assert_eq!(loaded.last_finalized_slot, saved.last_finalized_slot);
+        assert_eq!(loaded.checkpoint, saved.checkpoint);
+        assert_eq!(loaded.output_offset, Some(42));
+        assert_eq!(loaded.digest_offset, None);
+        assert!(!dir
+            .path()
+            .join(format!("{}.tmp", MANIFEST_FILE_NAME))
+            .exists());
+    }
+
+    #[test]
+    fn missing_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(RunManifest::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn truncate_to_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outputs.ndjson");
+        std::fs::write(&path, "slot 1\nslot 2\n").unwrap();
+        let offset = file_len(Some(&path));
+        assert_eq!(offset, Some(14));
+
+        // written after the checkpoint
+        std::fs::write(&path, "slot 1\nslot 2\nslot 3\n").unwrap();
+        truncate(Some(&path), offset);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "slot 1\nslot 2\n");
+
+        // nothing written after the checkpoint
+        truncate(Some(&path), offset);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "slot 1\nslot 2\n");
+    }
+
+    #[test]
+    fn file_len_of_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(file_len(Some(&dir.path().join("digest.tsv"))), Some(0));
+        assert_eq!(file_len(None), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use massa_execution_exports::{ExecutionOutput, SlotExecutionOutput};
+use massa_models::slot::Slot;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{trace, warn};
+
+#[derive(Debug)]
+pub enum WaitError {
+    /// No finalized slot received during the whole timeout
+    Stalled {
+        waiting_for: Slot,
+        last_finalized: Option<Slot>,
+    },
+    /// Execution worker dropped the broadcast channel
+    Closed {
+        waiting_for: Slot,
+        last_finalized: Option<Slot>,
+    },
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::Stalled {
+                waiting_for,
+                last_finalized,
+            } => write!(
+                f,
+                "execution stalled while waiting for slot {} (last finalized slot: {:?})",
+                waiting_for, last_finalized
+            ),
+            WaitError::Closed {
+                waiting_for,
+                last_finalized,
+            } => write!(
+                f,
+                "execution output channel closed while waiting for slot {} (last finalized slot: {:?})",
+                waiting_for, last_finalized
+            ),
+        }
+    }
+}
+
+/// Listen to the slot execution outputs broadcast by the execution worker
+/// and forward the finalized ones
+pub struct SlotExecutionListener {
+    /// Outputs with the time they were received
+    receiver: mpsc::Receiver<(ExecutionOutput, Instant)>,
+    timeout: Duration,
+    last_finalized: Option<Slot>,
+}
+
+impl SlotExecutionListener {
+    /// Subscribe to the broadcast channel
+    ///
+    /// Note: must be called before starting the execution worker, outputs sent before the
+    ///       subscription are not received
+    pub fn new(sender: &broadcast::Sender<SlotExecutionOutput>, timeout: Duration) -> Self {
+        let mut broadcast_receiver = sender.subscribe();
+        let (tx, receiver) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("slot_execution_listener".to_string())
+            .spawn(move || loop {
+                match broadcast_receiver.blocking_recv() {
+                    Ok(SlotExecutionOutput::FinalizedSlot(output)) => {
+                        trace!("Received finalized slot: {}", output.slot);
+                        if tx.send((output, Instant::now())).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(SlotExecutionOutput::ExecutedSlot(_)) => {}
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Slot execution listener lagged, {} outputs lost", count);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            })
+            .expect("Failed to spawn slot execution listener thread");
+
+        Self {
+            receiver,
+            timeout,
+            last_finalized: None,
+        }
+    }
+
+    /// Wait until the given slot is finalized, calling `on_output` for every finalized slot
+    /// received in between (with the time it was received)
+    ///
+    /// Fails if no finalized slot is received during `timeout`
+    pub fn wait_for<F>(&mut self, slot: Slot, mut on_output: F) -> Result<(), WaitError>
+    where
+        F: FnMut(ExecutionOutput, Instant),
+    {
+        while self.last_finalized.map_or(true, |last| last < slot) {
+            match self.receiver.recv_timeout(self.timeout) {
+                Ok((output, received_at)) => {
+                    self.last_finalized = Some(output.slot);
+                    on_output(output, received_at);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(WaitError::Stalled {
+                        waiting_for: slot,
+                        last_finalized: self.last_finalized,
+                    })
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(WaitError::Closed {
+                        waiting_for: slot,
+                        last_finalized: self.last_finalized,
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+}
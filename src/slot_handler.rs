@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use massa_execution_exports::{ExecutionController, ExecutionOutput};
+use massa_final_state::FinalStateController;
+use massa_models::address::Address;
+use massa_models::denunciation::DenunciationIndex;
+use massa_models::slot::Slot;
+use parking_lot::RwLock;
+use tracing::{trace, warn};
+
+use crate::args::ReplayArgs;
+use crate::digest::{read_digest_file, DigestWriter, StateDigest, StateDigester};
+use crate::gas_meter::GasMeter;
+use crate::grpc_conv::DumpedBlock;
+use crate::perf::PerfRecorder;
+use crate::slot_output_writer::SlotOutputWriter;
+
+/// Process the slots as they are finalized by the execution worker
+pub struct FinalizedSlotHandler {
+    final_state: Arc<RwLock<dyn FinalStateController>>,
+    digester: Option<StateDigester>,
+    digest_writer: Option<DigestWriter>,
+    reference_digests: Option<BTreeMap<Slot, StateDigest>>,
+    divergent_slot: Option<Slot>,
+    output_writer: Option<SlotOutputWriter>,
+    /// Slots before are replayed silently (nothing recorded)
+    from_slot: Option<Slot>,
+    /// Number of denunciations executed (slashing applied)
+    executed_denunciations: usize,
+    /// Address denounced by the denunciations of the fed blocks (until executed)
+    denounced: HashMap<DenunciationIndex, Address>,
+    perf: Option<PerfRecorder>,
+    gas_meter: Option<GasMeter>,
+    /// Gas used by the smart contract operations of the fed blocks (until finalized)
+    gas_used: BTreeMap<Slot, BTreeMap<String, Option<u64>>>,
+}
+
+impl FinalizedSlotHandler {
+    pub fn new(
+        final_state: Arc<RwLock<dyn FinalStateController>>,
+        execution_controller: Box<dyn ExecutionController>,
+        replay_arg: &ReplayArgs,
+        from_slot: Option<Slot>,
+    ) -> Self {
+        let digest_writer = replay_arg.digest_path.as_ref().map(|digest_path| {
+            println!("Writing state digests to: {:?}", digest_path);
+            DigestWriter::new(digest_path)
+        });
+        let reference_digests = replay_arg.reference_digest.as_ref().map(|reference_path| {
+            println!("Checking state digests against: {:?}", reference_path);
+            read_digest_file(reference_path)
+                .unwrap_or_else(|e| panic!("Failed to read reference digest file: {}", e))
+        });
+
+        let output_writer = replay_arg.output_path.as_ref().map(|output_path| {
+            println!("Writing slot execution outputs to: {:?}", output_path);
+            SlotOutputWriter::new(output_path)
+        });
+
+        let perf = replay_arg
+            .perf_report_path
+            .as_ref()
+            .map(|perf_report_path| {
+                println!("Writing performance report to: {:?}", perf_report_path);
+                PerfRecorder::new(&*final_state.read())
+            });
+
+        let gas_meter = replay_arg.measure_gas.then(|| {
+            println!("Measuring the gas used by the smart contract operations");
+            GasMeter::new(execution_controller)
+        });
+
+        let digester = (digest_writer.is_some() || reference_digests.is_some())
+            .then(|| StateDigester::new(&**final_state.read().get_database().read()));
+
+        Self {
+            final_state,
+            digester,
+            digest_writer,
+            reference_digests,
+            divergent_slot: None,
+            output_writer,
+            from_slot,
+            executed_denunciations: 0,
+            denounced: HashMap::new(),
+            perf,
+            gas_meter,
+            gas_used: BTreeMap::new(),
+        }
+    }
+
+    fn is_recording(&self, slot: Slot) -> bool {
+        self.from_slot.map_or(true, |from_slot| slot >= from_slot)
+    }
+
+    /// True if the final state must be observed at the end of the given slot
+    /// (blocks are then fed to the execution one at a time)
+    ///
+    /// Note: the gas meter needs every previous slot finalized before a block is fed,
+    /// including the silently replayed ones just before --from_slot
+    pub fn step_by_step(&self, slot: Slot) -> bool {
+        self.reference_digests.is_some()
+            || self.gas_meter.is_some()
+            || (self.digest_writer.is_some() && self.is_recording(slot))
+    }
+
+    /// First slot where the final state diverged from the reference digests
+    pub fn divergent_slot(&self) -> Option<Slot> {
+        self.divergent_slot
+    }
+
+    /// Block of the slot, fed to the execution
+    pub fn on_block(&mut self, slot: Slot, dumped_block: &DumpedBlock) {
+        let denunciations = &dumped_block.block.content.header.content.denunciations;
+        for denunciation in denunciations.iter() {
+            self.denounced.insert(
+                DenunciationIndex::from(denunciation),
+                Address::from_public_key(denunciation.get_public_key()),
+            );
+        }
+        if let Some(perf) = self.perf.as_mut() {
+            perf.on_block(slot, dumped_block);
+        }
+        // the previous slots are finalized (see step_by_step)
+        if let Some(gas_meter) = self.gas_meter.as_ref().filter(|_| self.is_recording(slot)) {
+            self.gas_used.insert(slot, gas_meter.measure(dumped_block));
+        }
+    }
+
+    /// The slot can be finalized by the execution from now on
+    pub fn on_fed(&mut self, slot: Slot) {
+        if let Some(perf) = self.perf.as_mut() {
+            perf.on_fed(slot);
+        }
+    }
+
+    pub fn on_finalized(&mut self, output: ExecutionOutput, received_at: Instant) {
+        trace!("Slot {} finalized", output.slot);
+        let recording = self.is_recording(output.slot);
+        let gas_used = self.gas_used.remove(&output.slot);
+
+        if let Some(perf) = self.perf.as_mut() {
+            perf.on_finalized(&output, gas_used.as_ref(), received_at, recording);
+        }
+
+        if let Some(writer) = self.output_writer.as_mut().filter(|_| recording) {
+            writer.write(&output, gas_used.as_ref());
+        }
+
+        self.handle_digest(&output, recording);
+        self.report_slashing(&output);
+    }
+
+    /// Print the denunciations executed at this slot and the roll changes & deferred credits
+    /// of the denounced addresses
+    fn report_slashing(&mut self, output: &ExecutionOutput) {
+        let denunciations = &output.state_changes.executed_denunciations_changes;
+        if denunciations.is_empty() {
+            return;
+        }
+        self.executed_denunciations += denunciations.len();
+        println!(
+            "Slot {}: {} denunciations executed (slashing)",
+            output.slot,
+            denunciations.len()
+        );
+        let pos_changes = &output.state_changes.pos_changes;
+        for index in denunciations.iter() {
+            let Some(address) = self.denounced.remove(index) else {
+                println!(
+                    "  {:?}: denunciation not found in the replayed blocks",
+                    index
+                );
+                continue;
+            };
+            let credits: Vec<_> = pos_changes
+                .deferred_credits
+                .credits
+                .iter()
+                .filter_map(|(slot, credits)| {
+                    credits
+                        .get(&address)
+                        .map(|amount| format!("{} at slot {}", amount, slot))
+                })
+                .collect();
+            println!(
+                "  {:?}: address {}, rolls {}, deferred credits [{}]",
+                index,
+                address,
+                pos_changes
+                    .roll_changes
+                    .get(&address)
+                    .map_or("unchanged".to_string(), |rolls| rolls.to_string()),
+                credits.join(", ")
+            );
+        }
+    }
+
+    /// Number of denunciations executed since the start of the replay
+    pub fn executed_denunciations(&self) -> usize {
+        self.executed_denunciations
+    }
+
+    /// Write the performance report (with --perf_report)
+    pub fn write_perf_report(&self, path: &Path) {
+        let Some(perf) = self.perf.as_ref() else {
+            return;
+        };
+        let final_state_hash = self
+            .final_state
+            .read()
+            .get_database()
+            .read()
+            .get_xof_db_hash()
+            .to_string();
+        let report = perf.report(final_state_hash);
+        report.print();
+        if let Err(e) = report.write(path) {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Update the state digest, write it and compare it with the reference
+    fn handle_digest(&mut self, output: &ExecutionOutput, recording: bool) {
+        if let Some(digester) = self.digester.as_mut() {
+            // Note: holding the lock prevents the execution from finalizing the next slot
+            let final_state = self.final_state.read();
+            let db = final_state.get_database().read();
+            digester.update_addresses(&**db, output.state_changes.ledger_changes.0.keys());
+
+            let reference = self
+                .reference_digests
+                .as_ref()
+                .and_then(|digests| digests.get(&output.slot));
+            let digest_writer = self.digest_writer.as_mut().filter(|_| recording);
+            if reference.is_none() && digest_writer.is_none() {
+                return;
+            }
+
+            let digest = if db.get_change_id().ok() == Some(output.slot) {
+                digester.digest(&**db, output.slot)
+            } else {
+                warn!(
+                    "Final state already past slot {}, digest not available",
+                    output.slot
+                );
+                StateDigest::not_observed(output.slot)
+            };
+
+            if let Some(writer) = digest_writer {
+                writer.write(&digest);
+            }
+
+            if let Some(reference) = reference {
+                let diff = reference.diff(&digest);
+                if !diff.is_empty() && self.divergent_slot.is_none() {
+                    println!(
+                        "Final state diverged at slot {} ({} differ)",
+                        output.slot,
+                        diff.join(", ")
+                    );
+                    println!("  reference: {}", reference);
+                    println!("  replayed:  {}", digest);
+                    println!("State changes: {:#?}", output.state_changes);
+                    self.divergent_slot = Some(output.slot);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use massa_async_pool::{AsyncMessage, AsyncMessageUpdate};
+use massa_execution_exports::ExecutionOutput;
+use massa_final_state::StateChanges;
+use massa_ledger_exports::{LedgerEntry, LedgerEntryUpdate};
+use massa_models::denunciation::DenunciationIndex;
+use massa_models::types::{SetOrDelete, SetOrKeep, SetUpdateOrDelete};
+use serde_json::{json, Map, Value};
+
+/// Write every finalized slot execution output as a json line
+pub struct SlotOutputWriter {
+    writer: BufWriter<std::fs::File>,
+}
+
+impl SlotOutputWriter {
+    pub fn new(path: &Path) -> Self {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Failed to open output file {:?}: {}", path, e));
+        Self {
+            writer: BufWriter::new(file),
+        }
+    }
+
+    /// Gas used by the smart contract operations of the slot, with --measure_gas
+    pub fn write(
+        &mut self,
+        output: &ExecutionOutput,
+        gas_used: Option<&BTreeMap<String, Option<u64>>>,
+    ) {
+        let line = slot_output_to_json(output, gas_used);
+        serde_json::to_writer(&mut self.writer, &line).expect("Failed to write output file");
+        self.writer
+            .write_all(b"\n")
+            .and_then(|_| self.writer.flush())
+            .expect("Failed to write output file");
+    }
+}
+
+fn slot_output_to_json(
+    output: &ExecutionOutput,
+    gas_used: Option<&BTreeMap<String, Option<u64>>>,
+) -> Value {
+    json!({
+        "slot": {
+            "period": output.slot.period,
+            "thread": output.slot.thread,
+        },
+        "block_id": output.block_info.as_ref().map(|info| info.block_id.to_string()),
+        "events": output.events.0,
+        "state_changes": state_changes_to_json(&output.state_changes),
+        // null: not measured, per operation id (null: the measure failed)
+        "gas_used": gas_used,
+    })
+}
+
+fn state_changes_to_json(state_changes: &StateChanges) -> Value {
+    let mut ledger_changes: Vec<_> = state_changes
+        .ledger_changes
+        .0
+        .iter()
+        .map(|(address, change)| (address.to_string(), ledger_change_to_json(change)))
+        .collect();
+    ledger_changes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut roll_changes: Vec<_> = state_changes
+        .pos_changes
+        .roll_changes
+        .iter()
+        .map(|(address, rolls)| (address.to_string(), *rolls))
+        .collect();
+    roll_changes.sort();
+
+    let mut production_stats: Vec<_> = state_changes
+        .pos_changes
+        .production_stats
+        .iter()
+        .map(|(address, stats)| {
+            json!({
+                "address": address.to_string(),
+                "block_success_count": stats.block_success_count,
+                "block_failure_count": stats.block_failure_count,
+            })
+        })
+        .collect();
+    production_stats.sort_by_key(|stats| stats["address"].as_str().unwrap_or_default().to_string());
+
+    let mut deferred_credits = Vec::new();
+    for (slot, credits) in state_changes.pos_changes.deferred_credits.credits.iter() {
+        let mut credits: Vec<_> = credits
+            .iter()
+            .map(|(address, amount)| (address.to_string(), amount.to_string()))
+            .collect();
+        credits.sort();
+        deferred_credits.extend(credits.into_iter().map(|(address, amount)| {
+            json!({ "slot": slot.to_string(), "address": address, "amount": amount })
+        }));
+    }
+
+    let mut executed_ops: Vec<_> = state_changes
+        .executed_ops_changes
+        .iter()
+        .map(|(op_id, (success, expiry_slot))| {
+            json!({
+                "operation_id": op_id.to_string(),
+                "success": success,
+                "expiry_slot": expiry_slot.to_string(),
+            })
+        })
+        .collect();
+    executed_ops.sort_by_key(|op| op["operation_id"].as_str().unwrap_or_default().to_string());
+
+    let mut executed_denunciations: Vec<_> = state_changes
+        .executed_denunciations_changes
+        .iter()
+        .map(|index| match index {
+            DenunciationIndex::BlockHeader { slot } => {
+                json!({ "kind": "block_header", "slot": slot.to_string() })
+            }
+            DenunciationIndex::Endorsement { slot, index } => {
+                json!({ "kind": "endorsement", "slot": slot.to_string(), "index": index })
+            }
+        })
+        .collect();
+    executed_denunciations.sort_by_key(|denunciation| denunciation.to_string());
+
+    json!({
+        "ledger": ledger_changes
+            .into_iter()
+            .map(|(address, change)| json!({ "address": address, "change": change }))
+            .collect::<Vec<_>>(),
+        "async_pool": state_changes
+            .async_pool_changes
+            .0
+            .iter()
+            .map(|((_, emission_slot, emission_index), change)| {
+                json!({
+                    "emission_slot": emission_slot.to_string(),
+                    "emission_index": emission_index,
+                    "change": async_message_change_to_json(change),
+                })
+            })
+            .collect::<Vec<_>>(),
+        "pos": {
+            "roll_changes": roll_changes
+                .into_iter()
+                .map(|(address, rolls)| json!({ "address": address, "rolls": rolls }))
+                .collect::<Vec<_>>(),
+            "production_stats": production_stats,
+            "deferred_credits": deferred_credits,
+        },
+        "executed_ops": executed_ops,
+        "executed_denunciations": executed_denunciations,
+    })
+}
+
+/// Bytes (bytecode, datastore keys & values) as an hex string
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Amounts & slots as strings
+fn string<T: ToString>(value: &T) -> Value {
+    Value::String(value.to_string())
+}
+
+/// Value if set, null if kept
+fn set_or_keep_to_json<T>(value: &SetOrKeep<T>, to_json: impl Fn(&T) -> Value) -> Value {
+    match value {
+        SetOrKeep::Set(value) => to_json(value),
+        SetOrKeep::Keep => Value::Null,
+    }
+}
+
+fn ledger_change_to_json(change: &SetUpdateOrDelete<LedgerEntry, LedgerEntryUpdate>) -> Value {
+    match change {
+        SetUpdateOrDelete::Set(entry) => json!({
+            "set": {
+                "balance": entry.balance.to_string(),
+                "bytecode": hex(&entry.bytecode.0),
+                "datastore": entry
+                    .datastore
+                    .iter()
+                    .map(|(key, value)| (hex(key), Value::String(hex(value))))
+                    .collect::<Map<_, _>>(),
+            }
+        }),
+        SetUpdateOrDelete::Update(update) => json!({
+            "update": {
+                "balance": set_or_keep_to_json(&update.balance, string),
+                "bytecode": set_or_keep_to_json(&update.bytecode, |b| json!(hex(&b.0))),
+                // null: key deleted
+                "datastore": update
+                    .datastore
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            SetOrDelete::Set(value) => Value::String(hex(value)),
+                            SetOrDelete::Delete => Value::Null,
+                        };
+                        (hex(key), value)
+                    })
+                    .collect::<Map<_, _>>(),
+            }
+        }),
+        SetUpdateOrDelete::Delete => json!("delete"),
+    }
+}
+
+fn async_message_change_to_json(
+    change: &SetUpdateOrDelete<AsyncMessage, AsyncMessageUpdate>,
+) -> Value {
+    match change {
+        SetUpdateOrDelete::Set(message) => json!({
+            "set": {
+                "sender": message.sender.to_string(),
+                "destination": message.destination.to_string(),
+                "function": message.function,
+                "max_gas": message.max_gas,
+                "fee": message.fee.to_string(),
+                "coins": message.coins.to_string(),
+                "validity_start": message.validity_start.to_string(),
+                "validity_end": message.validity_end.to_string(),
+                "function_params": hex(&message.function_params),
+                "trigger": message.trigger.as_ref().map(|trigger| json!({
+                    "address": trigger.address.to_string(),
+                    "datastore_key": trigger.datastore_key.as_deref().map(hex),
+                })),
+                "can_be_executed": message.can_be_executed,
+            }
+        }),
+        SetUpdateOrDelete::Update(update) => json!({
+            // null: field not updated
+            "update": {
+                "max_gas": set_or_keep_to_json(&update.max_gas, |max_gas| json!(max_gas)),
+                "fee": set_or_keep_to_json(&update.fee, string),
+                "coins": set_or_keep_to_json(&update.coins, string),
+                "validity_start": set_or_keep_to_json(&update.validity_start, string),
+                "validity_end": set_or_keep_to_json(&update.validity_end, string),
+                "can_be_executed": set_or_keep_to_json(&update.can_be_executed, |b| json!(b)),
+            }
+        }),
+        SetUpdateOrDelete::Delete => json!("delete"),
+    }
+}
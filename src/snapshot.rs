@@ -0,0 +1,195 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::{remove_dir_all, rename};
+use std::path::{Path, PathBuf};
+
+use copy_dir::copy_dir;
+use glob::glob;
+use massa_db_exports::MassaDBController;
+use massa_hash::{HashXof, HASH_XOF_SIZE_BYTES};
+use massa_models::config::{PERIODS_PER_CYCLE, THREAD_COUNT};
+use massa_models::slot::Slot;
+
+use crate::config::get_db_config;
+use crate::wrapped_massa_db::WrappedMassaDB;
+
+/// A db backup (`backup_*_*` folder) found in a rocks_db folder
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub slot: Slot,
+    pub hash: HashXof<HASH_XOF_SIZE_BYTES>,
+}
+
+pub fn snapshot_pattern(db_path: &Path) -> String {
+    db_path.join("backup_*_*").to_str().unwrap().to_string()
+}
+
+pub fn read_snapshot(path: PathBuf) -> Snapshot {
+    let db_config_from_backup = get_db_config(path.clone());
+    let wrapped_db = WrappedMassaDB::new(db_config_from_backup, false);
+    Snapshot {
+        slot: wrapped_db.0.get_change_id().unwrap(),
+        hash: wrapped_db.0.get_xof_db_hash(),
+        path,
+    }
+}
+
+/// Read every backup found in db_path, sorted by slot
+pub fn list_snapshots(db_path: &Path) -> Vec<Snapshot> {
+    let pattern = snapshot_pattern(db_path);
+    let glob_res = glob(&pattern).expect("Failed to read glob pattern");
+
+    let mut snapshots: Vec<Snapshot> = glob_res
+        .filter_map(|entry| match entry {
+            Ok(path) => Some(read_snapshot(path)),
+            Err(e) => {
+                println!("Error: {:?}", e);
+                None
+            }
+        })
+        .collect();
+
+    snapshots.sort_by_key(|s| s.slot);
+    snapshots
+}
+
+/// Newest backup at or before the given slot
+pub fn select_snapshot(snapshots: &[Snapshot], slot: Slot) -> Option<&Snapshot> {
+    snapshots
+        .iter()
+        .filter(|s| s.slot <= slot)
+        .max_by_key(|s| s.slot)
+}
+
+/// Compare the replayed final state hash with the backups taken later than the replay start
+pub struct SnapshotChecker {
+    pending: VecDeque<Snapshot>,
+    matches: usize,
+    mismatches: usize,
+}
+
+impl SnapshotChecker {
+    pub fn new(snapshots: Vec<Snapshot>, start_slot: Slot) -> Self {
+        let pending: VecDeque<Snapshot> = snapshots
+            .into_iter()
+            .filter(|s| s.slot > start_slot)
+            .collect();
+        println!(
+            "Final state hash will be checked against {} later backup(s)",
+            pending.len()
+        );
+        Self {
+            pending,
+            matches: 0,
+            mismatches: 0,
+        }
+    }
+
+    /// Slot of the next backup to check against
+    pub fn next_slot(&self) -> Option<Slot> {
+        self.pending.front().map(|s| s.slot)
+    }
+
+    /// Check the backups up to `slot`, given the final state hash at `slot`
+    pub fn check(&mut self, slot: Slot, hash: &HashXof<HASH_XOF_SIZE_BYTES>) {
+        while let Some(snapshot) = self.pending.front() {
+            if snapshot.slot > slot {
+                break;
+            }
+            let snapshot = self.pending.pop_front().unwrap();
+
+            if snapshot.slot < slot {
+                println!(
+                    "Backup {:?} (slot {}): not checked, final state was not observed at this slot",
+                    snapshot.path, snapshot.slot
+                );
+            } else if snapshot.hash == *hash {
+                println!(
+                    "Backup {:?} (slot {}): hash match ({})",
+                    snapshot.path, snapshot.slot, hash
+                );
+                self.matches += 1;
+            } else {
+                println!(
+                    "Backup {:?} (slot {}): hash MISMATCH, expected: {}, replayed: {}",
+                    snapshot.path, snapshot.slot, snapshot.hash, hash
+                );
+                self.mismatches += 1;
+            }
+        }
+    }
+
+    /// Print a summary and return true if no divergence was found
+    pub fn report(&self) -> bool {
+        for snapshot in self.pending.iter() {
+            println!(
+                "Backup {:?} (slot {}): not reached by the replay",
+                snapshot.path, snapshot.slot
+            );
+        }
+        println!(
+            "Backup checks: {} match(es), {} mismatch(es)",
+            self.matches, self.mismatches
+        );
+        self.mismatches == 0
+    }
+}
+
+/// Write db backups (`backup_<period>_<thread>` folders) during a replay
+pub struct SnapshotWriter {
+    folder: PathBuf,
+    slots: BTreeSet<Slot>,
+    every_cycle: bool,
+}
+
+impl SnapshotWriter {
+    pub fn new(folder: PathBuf, slots: BTreeSet<Slot>, every_cycle: bool) -> Self {
+        Self {
+            folder,
+            slots,
+            every_cycle,
+        }
+    }
+
+    /// True if a backup must be written at the end of the given slot
+    pub fn wants(&self, slot: Slot) -> bool {
+        let is_cycle_end = slot.period % PERIODS_PER_CYCLE == PERIODS_PER_CYCLE - 1
+            && slot.thread == THREAD_COUNT - 1;
+        self.slots.contains(&slot) || (self.every_cycle && is_cycle_end)
+    }
+
+    /// Write a backup of the db (must be at the end of `slot`)
+    pub fn write(&self, db: &dyn MassaDBController, slot: Slot) {
+        let backup_path = backup_path(&self.folder, slot);
+        if backup_path.exists() {
+            println!(
+                "Backup {:?} already exists, not overwritten",
+                backup_path.display()
+            );
+            return;
+        }
+
+        write_backup(db, slot, &backup_path);
+        println!(
+            "Backup (path: {:?}): hash: {}, last slot: {}",
+            backup_path.display(),
+            db.get_xof_db_hash(),
+            slot
+        );
+    }
+}
+
+pub fn backup_path(folder: &Path, slot: Slot) -> PathBuf {
+    folder.join(format!("backup_{}_{}", slot.period, slot.thread))
+}
+
+/// Write a backup of the db (must be at the end of `slot`) to backup_path
+pub fn write_backup(db: &dyn MassaDBController, slot: Slot, backup_path: &Path) {
+    // Note: backup_db creates a checkpoint next to the db, move it to the backup folder
+    let checkpoint_path = db.backup_db(slot);
+    if rename(&checkpoint_path, backup_path).is_err() {
+        copy_dir(&checkpoint_path, backup_path)
+            .unwrap_or_else(|e| panic!("Unable to copy backup to {:?}: {}", backup_path, e));
+        remove_dir_all(&checkpoint_path).expect("Unable to remove db checkpoint");
+    }
+}
@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use clap::ValueEnum;
+
+use crate::args::SweepArgs;
+use crate::config::{network, node_settings, NodeSettings};
+use crate::perf::PerfReport;
+use crate::EXIT_DIVERGENCE;
+
+/// Execution cache settings of a run
+#[derive(Debug, Clone, Copy)]
+struct CacheSettings {
+    lru_cache_size: u32,
+    hd_cache_size: usize,
+    snip_amount: usize,
+}
+
+struct SweepRun {
+    settings: CacheSettings,
+    wall_time_secs: f64,
+    report: PerfReport,
+}
+
+/// The given values, or the node setting if no value is given
+fn values_or<T: Copy>(values: &[T], default: T) -> Vec<T> {
+    if values.is_empty() {
+        vec![default]
+    } else {
+        values.to_vec()
+    }
+}
+
+/// Every combination of the given values
+fn parameter_matrix(args: &SweepArgs) -> Vec<CacheSettings> {
+    let execution = &node_settings().execution;
+    let mut matrix = Vec::new();
+    for lru_cache_size in values_or(&args.lru_cache_sizes, execution.lru_cache_size) {
+        for hd_cache_size in values_or(&args.hd_cache_sizes, execution.hd_cache_size) {
+            for snip_amount in values_or(&args.snip_amounts, execution.snip_amount) {
+                matrix.push(CacheSettings {
+                    lru_cache_size,
+                    hd_cache_size,
+                    snip_amount,
+                });
+            }
+        }
+    }
+    matrix
+}
+
+/// Write the node config of a run: the current settings with the cache settings of the run
+fn write_node_config(run_dir: &Path, settings: CacheSettings) -> PathBuf {
+    let mut run_settings: NodeSettings = node_settings().clone();
+    run_settings.execution.lru_cache_size = settings.lru_cache_size;
+    run_settings.execution.hd_cache_size = settings.hd_cache_size;
+    run_settings.execution.snip_amount = settings.snip_amount;
+
+    let path = run_dir.join("node_config.toml");
+    let content = toml::to_string_pretty(&run_settings).expect("Failed to serialize node settings");
+    std::fs::write(&path, content).expect("Failed to write node config");
+    path
+}
+
+/// Run a replay with the given node config (in a child process, so each replay starts from a clean state)
+fn run_replay(
+    db_path: &Path,
+    initial_rolls_path: &Path,
+    args: &SweepArgs,
+    run_dir: &Path,
+    node_config: &Path,
+    perf_report: &Path,
+) -> bool {
+    let exe = std::env::current_exe().expect("Cannot find current executable");
+    let mut command = Command::new(exe);
+    command
+        .arg("--path")
+        .arg(db_path)
+        .arg("--initial_roll_path")
+        .arg(initial_rolls_path)
+        .args(network().args())
+        .arg("--node_config")
+        .arg(node_config)
+        .arg("replay")
+        .arg("--blocks")
+        .arg(&args.dump_block_path)
+        .arg("--blocks_format")
+        .arg(args.blocks_format.to_possible_value().unwrap().get_name())
+        .arg("--backup")
+        .arg(&args.db_backup_path)
+        .arg("--execution_timeout")
+        .arg(args.execution_timeout.to_string())
+        .arg("--perf_report")
+        .arg(perf_report)
+        // every run starts with an empty module cache of its own
+        .arg("--module_cache")
+        .arg(run_dir.join("module_cache"));
+    if let Some((period, thread)) = args.until_slot {
+        command
+            .arg("--until_slot")
+            .arg(format!("{},{}", period, thread));
+    }
+
+    let status = command.status().expect("Failed to run replay");
+    if !status.success() {
+        eprintln!("Replay failed (exit code: {:?})", status.code());
+    }
+    status.success()
+}
+
+/// Replay the same snapshot & blocks for every combination of execution cache settings,
+/// and print a comparison table (the final state hash must be the same for every run)
+pub fn sweep(db_path: &Path, initial_rolls_path: &Path, args: &SweepArgs) {
+    let matrix = parameter_matrix(args);
+
+    // keep the temp dir alive until the end of the sweep
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let output_dir: PathBuf = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| temp_dir.path().to_path_buf());
+
+    let mut runs = Vec::new();
+    for (i, settings) in matrix.into_iter().enumerate() {
+        let run_dir = output_dir.join(format!("run_{}", i));
+        if run_dir.exists() {
+            std::fs::remove_dir_all(&run_dir).expect("Unable to clean run folder");
+        }
+        std::fs::create_dir_all(&run_dir).expect("Unable to create run folder");
+        let node_config = write_node_config(&run_dir, settings);
+        let perf_report = run_dir.join("perf.json");
+
+        println!("Run {}: {:?}...", i, settings);
+        let started_at = Instant::now();
+        if !run_replay(
+            db_path,
+            initial_rolls_path,
+            args,
+            &run_dir,
+            &node_config,
+            &perf_report,
+        ) {
+            std::process::exit(1);
+        }
+        let wall_time_secs = started_at.elapsed().as_secs_f64();
+        let report = PerfReport::read(&perf_report).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        runs.push(SweepRun {
+            settings,
+            wall_time_secs,
+            report,
+        });
+    }
+
+    println!(
+        "{:>3} {:>8} {:>8} {:>5} {:>9} {:>9} {:>9} {:>9} {:>10}  final state hash",
+        "run",
+        "lru",
+        "hd_cache",
+        "snip",
+        "wall (s)",
+        "p50 (ms)",
+        "p95 (ms)",
+        "p99 (ms)",
+        "rss (MB)"
+    );
+    for (i, run) in runs.iter().enumerate() {
+        println!(
+            "{:>3} {:>8} {:>8} {:>5} {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>10}  {}",
+            i,
+            run.settings.lru_cache_size,
+            run.settings.hd_cache_size,
+            run.settings.snip_amount,
+            run.wall_time_secs,
+            run.report.latency.p50_ms,
+            run.report.latency.p95_ms,
+            run.report.latency.p99_ms,
+            run.report
+                .peak_rss_kb
+                .map(|kb| (kb / 1024).to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            run.report.final_state_hash
+        );
+    }
+    if args.output_dir.is_some() {
+        println!("Node configs & perf reports kept in {:?}", output_dir);
+    }
+
+    let first_hash = runs.first().map(|run| &run.report.final_state_hash);
+    if runs
+        .iter()
+        .any(|run| Some(&run.report.final_state_hash) != first_hash)
+    {
+        eprintln!("Final state hash differs between runs");
+        std::process::exit(EXIT_DIVERGENCE);
+    }
+    println!("Same final state hash for every run");
+}
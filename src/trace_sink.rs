@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use massa_async_pool::AsyncMessage;
+use massa_execution_exports::types_trace_info::AbiTraceType;
+use massa_execution_exports::{
+    AbiTrace, ExecutionChannels, ExecutionOutput, SlotAbiCallStack, SlotExecutionOutput,
+};
+use massa_final_state::FinalStateController;
+use massa_models::address::Address;
+use massa_models::operation::OperationId;
+use massa_models::slot::Slot;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::async_message_tracker::AsyncMessageTracker;
+use crate::grpc_conv::DumpedBlock;
+
+/// Keep only the call stacks of these operations, or involving one of these addresses (keep all if empty)
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub addresses: Vec<String>,
+    pub operation_ids: Vec<String>,
+}
+
+impl TraceFilter {
+    fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.operation_ids.is_empty()
+    }
+
+    fn keep(&self, operation_id: Option<&str>, addresses: &BTreeSet<String>) -> bool {
+        self.is_empty()
+            || operation_id.map_or(false, |id| self.operation_ids.iter().any(|o| o == id))
+            || self.addresses.iter().any(|a| addresses.contains(a))
+    }
+}
+
+/// Addresses of a call stack: every parameter of an abi call or sub call holding an address
+/// (caller & target of the calls, sender & recipient of the transfers...)
+fn call_stack_addresses(call_stack: &[AbiTrace], addresses: &mut BTreeSet<String>) {
+    for trace in call_stack {
+        for parameter in trace.parameters.iter() {
+            if let AbiTraceType::String(value) = &parameter.value {
+                if Address::from_str(value).is_ok() {
+                    addresses.insert(value.clone());
+                }
+            }
+        }
+        if let Some(sub_calls) = trace.sub_calls.as_ref() {
+            call_stack_addresses(sub_calls, addresses);
+        }
+    }
+}
+
+/// Operation fed to the execution
+struct FedOperation {
+    sender: Address,
+    /// Sender, recipient of a transaction, target of a call...
+    addresses: BTreeSet<String>,
+}
+
+enum SinkEvent {
+    Block(Slot, HashMap<OperationId, FedOperation>),
+    Finalized(ExecutionOutput),
+    Traces(SlotAbiCallStack),
+}
+
+/// Write the abi call stacks of the finalized slots, one file per slot (`<period>_<thread>.ndjson`)
+/// with one json object per operation & async message
+pub struct TraceSink {
+    sender: mpsc::Sender<SinkEvent>,
+    last_slot: Arc<Mutex<Option<Slot>>>,
+}
+
+impl TraceSink {
+    /// Subscribe to the execution traces & outputs channels and start writing in a dedicated thread
+    ///
+    /// Note: must be called before starting the execution worker
+    pub fn start(
+        channels: &ExecutionChannels,
+        final_state: &dyn FinalStateController,
+        folder: PathBuf,
+        from_slot: Option<Slot>,
+        filter: TraceFilter,
+    ) -> Self {
+        create_dir_all(&folder)
+            .unwrap_or_else(|e| panic!("Failed to create trace folder {:?}: {}", folder, e));
+        let (sender, receiver) = mpsc::channel();
+
+        let mut traces_receiver = channels.slot_execution_traces_sender.subscribe();
+        let traces_sender = sender.clone();
+        thread::Builder::new()
+            .name("trace_sink_traces".to_string())
+            .spawn(move || loop {
+                match traces_receiver.blocking_recv() {
+                    Ok((slot_trace, _transfers, true)) => {
+                        if traces_sender.send(SinkEvent::Traces(slot_trace)).is_err() {
+                            break;
+                        }
+                    }
+                    // speculative execution
+                    Ok((_, _, false)) => {}
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Trace sink lagged, {} slot traces lost", count);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            })
+            .expect("Failed to spawn trace sink thread");
+
+        let mut output_receiver = channels.slot_execution_output_sender.subscribe();
+        let output_sender = sender.clone();
+        thread::Builder::new()
+            .name("trace_sink_outputs".to_string())
+            .spawn(move || loop {
+                match output_receiver.blocking_recv() {
+                    Ok(SlotExecutionOutput::FinalizedSlot(output)) => {
+                        if output_sender.send(SinkEvent::Finalized(output)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(SlotExecutionOutput::ExecutedSlot(_)) => {}
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Trace sink lagged, {} slot outputs lost", count);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            })
+            .expect("Failed to spawn trace sink thread");
+
+        let last_slot = Arc::new(Mutex::new(None));
+        let mut writer = SlotTraceWriter {
+            folder,
+            from_slot,
+            filter,
+            async_messages: AsyncMessageTracker::new(final_state),
+            operations: BTreeMap::new(),
+            executed_messages: BTreeMap::new(),
+            traces: BTreeMap::new(),
+            last_slot: last_slot.clone(),
+        };
+        thread::Builder::new()
+            .name("trace_sink".to_string())
+            .spawn(move || {
+                while let Ok(event) = receiver.recv() {
+                    writer.on_event(event);
+                }
+            })
+            .expect("Failed to spawn trace sink thread");
+
+        Self { sender, last_slot }
+    }
+
+    /// Block of the slot, fed to the execution: senders & addresses of its operations
+    pub fn on_block(&self, slot: Slot, dumped_block: &DumpedBlock) {
+        let operations = dumped_block
+            .operations
+            .iter()
+            .map(|op| {
+                let mut addresses: BTreeSet<String> = op
+                    .get_affected_addresses()
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect();
+                addresses.insert(op.content_creator_address.to_string());
+                let operation = FedOperation {
+                    sender: op.content_creator_address,
+                    addresses,
+                };
+                (op.id, operation)
+            })
+            .collect();
+        let _ = self.sender.send(SinkEvent::Block(slot, operations));
+    }
+
+    /// Wait (at most `timeout`) for the traces of the given slot to be written
+    pub fn wait_for(&self, slot: Slot, timeout: Duration) {
+        let start = Instant::now();
+        while self.last_slot.lock().map_or(true, |last| last < slot) {
+            if start.elapsed() > timeout {
+                println!(
+                    "Traces of slot {} not received (last slot with traces: {:?})",
+                    slot,
+                    *self.last_slot.lock()
+                );
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// Match the traces of a slot with its operations and executed async messages, and write them
+struct SlotTraceWriter {
+    folder: PathBuf,
+    from_slot: Option<Slot>,
+    filter: TraceFilter,
+    async_messages: AsyncMessageTracker,
+    /// Operations of the fed blocks (until the traces of their slot are written)
+    operations: BTreeMap<Slot, HashMap<OperationId, FedOperation>>,
+    /// Async messages executed at the finalized slots (until their traces are received)
+    executed_messages: BTreeMap<Slot, Vec<AsyncMessage>>,
+    /// Traces received before the output of their slot
+    traces: BTreeMap<Slot, SlotAbiCallStack>,
+    last_slot: Arc<Mutex<Option<Slot>>>,
+}
+
+impl SlotTraceWriter {
+    fn on_event(&mut self, event: SinkEvent) {
+        match event {
+            SinkEvent::Block(slot, operations) => {
+                self.operations.insert(slot, operations);
+            }
+            SinkEvent::Finalized(output) => {
+                let slot = output.slot;
+                let executed = self.async_messages.on_finalized(&output);
+                match self.traces.remove(&slot) {
+                    Some(slot_trace) => self.write_slot_trace(&slot_trace, executed),
+                    None => {
+                        self.executed_messages.insert(slot, executed);
+                    }
+                }
+            }
+            SinkEvent::Traces(slot_trace) => {
+                match self.executed_messages.remove(&slot_trace.slot) {
+                    Some(executed) => self.write_slot_trace(&slot_trace, executed),
+                    None => {
+                        self.traces.insert(slot_trace.slot, slot_trace);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_slot_trace(&mut self, slot_trace: &SlotAbiCallStack, executed: Vec<AsyncMessage>) {
+        let slot = slot_trace.slot;
+        // the operations of a block are executed at its slot
+        let operations = self.operations.remove(&slot).unwrap_or_default();
+        self.operations.retain(|block_slot, _| *block_slot > slot);
+
+        if self.from_slot.map_or(true, |from_slot| slot >= from_slot) {
+            let records = self.slot_records(slot_trace, &operations, &executed);
+            if !records.is_empty() {
+                self.write_records(slot, &records);
+            }
+        }
+        *self.last_slot.lock() = Some(slot);
+    }
+
+    fn slot_records(
+        &self,
+        slot_trace: &SlotAbiCallStack,
+        operations: &HashMap<OperationId, FedOperation>,
+        executed: &[AsyncMessage],
+    ) -> Vec<Value> {
+        let slot = json!({
+            "period": slot_trace.slot.period,
+            "thread": slot_trace.slot.thread,
+        });
+        let mut records = Vec::new();
+
+        let mut operation_call_stacks: Vec<_> = slot_trace.operation_call_stacks.iter().collect();
+        operation_call_stacks.sort_by_key(|(op_id, _)| op_id.to_string());
+        for (op_id, call_stack) in operation_call_stacks {
+            let operation = operations.get(op_id);
+            let mut addresses = operation
+                .map(|operation| operation.addresses.clone())
+                .unwrap_or_default();
+            call_stack_addresses(call_stack, &mut addresses);
+            let op_id = op_id.to_string();
+            if self.filter.keep(Some(&op_id), &addresses) {
+                records.push(json!({
+                    "slot": slot,
+                    "operation_id": op_id,
+                    "sender": operation.map(|operation| operation.sender.to_string()),
+                    "addresses": addresses,
+                    "call_stack": call_stack,
+                }));
+            }
+        }
+
+        // the messages are executed (and traced) in message id order
+        let messages_known = executed.len() == slot_trace.asc_call_stacks.len();
+        if !messages_known {
+            warn!(
+                "Slot {}: {} async message traces for {} executed messages, senders unknown",
+                slot_trace.slot,
+                slot_trace.asc_call_stacks.len(),
+                executed.len()
+            );
+        }
+        for (index, call_stack) in slot_trace.asc_call_stacks.iter().enumerate() {
+            let message = executed.get(index).filter(|_| messages_known);
+            let mut addresses = BTreeSet::new();
+            if let Some(message) = message {
+                addresses.insert(message.sender.to_string());
+                addresses.insert(message.destination.to_string());
+            }
+            call_stack_addresses(call_stack, &mut addresses);
+            if self.filter.keep(None, &addresses) {
+                records.push(json!({
+                    "slot": slot,
+                    "async_message_index": index,
+                    "sender": message.map(|message| message.sender.to_string()),
+                    "destination": message.map(|message| message.destination.to_string()),
+                    "function": message.map(|message| message.function.clone()),
+                    "addresses": addresses,
+                    "call_stack": call_stack,
+                }));
+            }
+        }
+        records
+    }
+
+    fn write_records(&self, slot: Slot, records: &[Value]) {
+        let path = self.folder.join(trace_file_name(slot));
+        let file = File::create(&path)
+            .unwrap_or_else(|e| panic!("Failed to create trace file {:?}: {}", path, e));
+        let mut writer = BufWriter::new(file);
+        records
+            .iter()
+            .try_for_each(|record| writeln!(writer, "{}", record))
+            .and_then(|_| writer.flush())
+            .unwrap_or_else(|e| panic!("Failed to write trace file {:?}: {}", path, e));
+    }
+}
+
+fn trace_file_name(slot: Slot) -> String {
+    format!("{}_{}.ndjson", slot.period, slot.thread)
+}
+
+fn parse_trace_file_name(file_name: &str) -> Option<Slot> {
+    let (period, thread) = file_name.strip_suffix(".ndjson")?.split_once('_')?;
+    Some(Slot::new(period.parse().ok()?, thread.parse().ok()?))
+}
+
+/// Remove the trace files of the slots after the given slot (written before a replay is resumed
+/// from a checkpoint), return the number of files removed
+pub fn remove_traces_after(folder: &Path, slot: Slot) -> std::io::Result<usize> {
+    if !folder.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for entry in std::fs::read_dir(folder)? {
+        let entry = entry?;
+        let after = entry
+            .file_name()
+            .to_str()
+            .and_then(parse_trace_file_name)
+            .map_or(false, |trace_slot| trace_slot > slot);
+        if after {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_traces_after_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        for slot in [Slot::new(10, 31), Slot::new(11, 0), Slot::new(11, 5)] {
+            std::fs::write(dir.path().join(trace_file_name(slot)), "{}\n").unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        assert_eq!(
+            remove_traces_after(dir.path(), Slot::new(11, 0)).unwrap(),
+            1
+        );
+        assert!(dir.path().join("10_31.ndjson").exists());
+        assert!(dir.path().join("11_0.ndjson").exists());
+        assert!(!dir.path().join("11_5.ndjson").exists());
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn trace_file_name_round_trip() {
+        let slot = Slot::new(4242, 17);
+        assert_eq!(parse_trace_file_name(&trace_file_name(slot)), Some(slot));
+        assert_eq!(parse_trace_file_name("4242_17.trace"), None);
+        assert_eq!(parse_trace_file_name("x_17.ndjson"), None);
+    }
+}
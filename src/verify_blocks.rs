@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+
+use massa_hash::Hash;
+use massa_models::block_id::BlockId;
+use massa_models::config::THREAD_COUNT;
+use massa_models::slot::Slot;
+use massa_proto_rs::massa::model::v1::{self as grpc_model};
+use massa_storage::Storage;
+use prost::Message;
+
+use crate::args::VerifyBlocksArgs;
+use crate::block_storage::BlockStorage;
+use crate::grpc_conv::{ConversionError, DumpedBlock};
+
+/// A check that failed on the dumped block of a slot
+pub struct BlockFailure {
+    pub slot: Slot,
+    pub reason: String,
+}
+
+/// Check the dumped blocks from `from_slot` to `until_slot` (included): decoding, signatures,
+/// ids (recomputed vs stored), operation merkle root, parents and round trip conversion
+///
+/// Note: a parent is only required in the dump if the dump has an earlier block in its thread
+pub fn verify_blocks(
+    block_db: &BlockStorage,
+    from_slot: Option<Slot>,
+    until_slot: Option<Slot>,
+) -> Vec<BlockFailure> {
+    let mut failures = Vec::new();
+    let mut block_ids: HashSet<BlockId> = HashSet::new();
+    let mut thread_has_block = vec![false; THREAD_COUNT as usize];
+
+    for slot in block_db.slots(from_slot, until_slot) {
+        let mut fail = |reason: String| failures.push(BlockFailure { slot, reason });
+
+        let content = match block_db.read(&slot) {
+            Some(Ok(content)) => content,
+            Some(Err(e)) => {
+                fail(e);
+                continue;
+            }
+            None => {
+                fail("cannot be read".to_string());
+                continue;
+            }
+        };
+        let filled_block = match grpc_model::FilledBlock::decode(&content[..]) {
+            Ok(filled_block) => filled_block,
+            Err(e) => {
+                fail(format!("cannot be decoded: {}", e));
+                continue;
+            }
+        };
+
+        let stored_header = filled_block.header.clone().unwrap_or_default();
+        let stored_endorsement_ids: Vec<String> = stored_header
+            .content
+            .as_ref()
+            .map(|c| {
+                c.endorsements
+                    .iter()
+                    .map(|e| e.secure_hash.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let stored_operation_ids: Vec<String> = filled_block
+            .operations
+            .iter()
+            .map(|entry| entry.operation_id.clone())
+            .collect();
+        let dumped_block = match DumpedBlock::try_from(filled_block) {
+            Ok(dumped_block) => dumped_block,
+            Err(e) => {
+                // the slot is already in the failure
+                let e = ConversionError { slot: None, ..e };
+                fail(format!("cannot be converted: {}", e));
+                continue;
+            }
+        };
+        let DumpedBlock { block, operations } = &dumped_block;
+        let header = &block.content.header;
+
+        if header.content.slot != slot {
+            fail(format!("header slot is {}", header.content.slot));
+        }
+        if block.id.to_string() != stored_header.secure_hash {
+            fail(format!(
+                "block id mismatch: recomputed {}, stored {}",
+                block.id, stored_header.secure_hash
+            ));
+        }
+        if let Err(e) = header.verify_signature() {
+            fail(format!("invalid block signature: {}", e));
+        }
+
+        for (endorsement, stored_id) in header
+            .content
+            .endorsements
+            .iter()
+            .zip(stored_endorsement_ids.iter())
+        {
+            if endorsement.id.to_string() != *stored_id {
+                fail(format!(
+                    "endorsement id mismatch: recomputed {}, stored {}",
+                    endorsement.id, stored_id
+                ));
+            }
+            if let Err(e) = endorsement.verify_signature() {
+                fail(format!(
+                    "invalid signature of endorsement {}: {}",
+                    endorsement.id, e
+                ));
+            }
+        }
+
+        let mut operation_ids_bytes = Vec::new();
+        for (operation, stored_id) in operations.iter().zip(stored_operation_ids.iter()) {
+            if operation.id.to_string() != *stored_id {
+                fail(format!(
+                    "operation id mismatch: recomputed {}, stored {}",
+                    operation.id, stored_id
+                ));
+            }
+            if let Err(e) = operation.verify_signature() {
+                fail(format!(
+                    "invalid signature of operation {}: {}",
+                    operation.id, e
+                ));
+            }
+            operation_ids_bytes.extend_from_slice(&operation.id.to_bytes()[..]);
+        }
+        let merkle_root = Hash::compute_from(&operation_ids_bytes);
+        if merkle_root != header.content.operation_merkle_root {
+            fail(format!(
+                "operation merkle root mismatch: computed {}, in header {}",
+                merkle_root, header.content.operation_merkle_root
+            ));
+        }
+
+        for (thread, parent) in header.content.parents.iter().enumerate() {
+            if thread_has_block[thread] && !block_ids.contains(parent) {
+                fail(format!(
+                    "parent {} (thread {}) not in the dump",
+                    parent, thread
+                ));
+            }
+        }
+
+        if let Err(reason) = check_round_trip(&dumped_block) {
+            fail(reason);
+        }
+
+        block_ids.insert(block.id);
+        thread_has_block[slot.thread as usize] = true;
+    }
+    failures
+}
+
+/// Convert the block back to a FilledBlock (operations read from a storage), then to massa types again,
+/// the result must be the same block
+fn check_round_trip(dumped_block: &DumpedBlock) -> Result<(), String> {
+    let mut storage = Storage::create_root();
+    storage.store_operations(dumped_block.operations.clone());
+    let round_trip = DumpedBlock::from_storage(dumped_block.block.clone(), &storage)
+        .map(|stored_block| grpc_model::FilledBlock::from(&stored_block))
+        .and_then(DumpedBlock::try_from)
+        .map_err(|e| format!("round trip conversion failed: {}", e))?;
+
+    let same_operations = round_trip
+        .operations
+        .iter()
+        .map(|op| (op.id, &op.serialized_data))
+        .eq(dumped_block
+            .operations
+            .iter()
+            .map(|op| (op.id, &op.serialized_data)));
+    if round_trip.block.id != dumped_block.block.id
+        || round_trip.block.serialized_data != dumped_block.block.serialized_data
+        || !same_operations
+    {
+        return Err(
+            "round trip conversion (massa -> gRPC -> massa) gives a different block".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Print the failures, return true if there is none
+pub fn report_failures(failures: &[BlockFailure]) -> bool {
+    for failure in failures.iter() {
+        println!("Slot {}: {}", failure.slot, failure.reason);
+    }
+    if !failures.is_empty() {
+        let mut slots: Vec<Slot> = failures.iter().map(|f| f.slot).collect();
+        slots.dedup();
+        println!(
+            "{} failures in {} dumped blocks",
+            failures.len(),
+            slots.len()
+        );
+    }
+    failures.is_empty()
+}
+
+pub fn verify_blocks_command(args: &VerifyBlocksArgs) {
+    let format = args
+        .blocks_format
+        .resolve(&args.dump_block_path)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let block_db = BlockStorage::open(&args.dump_block_path, format).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let from_slot = args
+        .from_slot
+        .map(|(period, thread)| Slot::new(period, thread));
+    let until_slot = args
+        .until_slot
+        .map(|(period, thread)| Slot::new(period, thread));
+    let failures = verify_blocks(&block_db, from_slot, until_slot);
+    if report_failures(&failures) {
+        println!("All dumped blocks are valid");
+    } else {
+        std::process::exit(1);
+    }
+}